@@ -0,0 +1,25 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+extern crate gamesolver;
+
+use gamesolver::games::connect4::Connect4;
+use gamesolver::games::stack4::Stack4;
+use gamesolver::games::Game;
+use gamesolver::perft::perft;
+
+fn connect4_perft(c: &mut Criterion) {
+    let mut board = Connect4::new();
+    c.bench_function("Connect4::perft, depth=6", |b| {
+        b.iter(|| black_box(perft(&mut board, 6)))
+    });
+}
+
+fn stack4_perft(c: &mut Criterion) {
+    let mut board = Stack4::new();
+    c.bench_function("Stack4::perft, depth=3", |b| {
+        b.iter(|| black_box(perft(&mut board, 3)))
+    });
+}
+
+criterion_group!(benches, connect4_perft, stack4_perft);
+criterion_main!(benches);
@@ -2,6 +2,8 @@ use crate::evaluators::Evaluator;
 use crate::games::{Game, Player};
 use crate::policies::Policy;
 use crate::search::*;
+use std::cell::RefCell;
+use std::time::Duration;
 
 pub trait Agent<G>
 where
@@ -11,27 +13,70 @@ where
     fn get_action_explored(&self, board: &G, player: Player) -> (G::Action, bool) {
         (self.get_action(board, player), false)
     }
+
+    // The search behind the action the agent picks, for agents that pick the best move of a
+    // search. Others give None.
+    fn search(&self, _board: &G, _player: Player) -> Option<SearchResult<G::Action>> {
+        None
+    }
 }
 
-pub struct MinimaxAgent<'a, T> {
+pub struct MinimaxAgent<'a, T, G: Game> {
     evaluator: &'a T,
     depth: u32,
+    // Time to think about every move, the search deepens until it runs out.
+    time: Option<Duration>,
+    // Kept between moves, so that the search for a move starts with what the searches for the
+    // earlier ones found.
+    tt: RefCell<TranspositionTable<G::Action, G::Uid>>,
 }
 
-impl<'a, T> MinimaxAgent<'a, T> {
+impl<'a, T, G: Game> MinimaxAgent<'a, T, G> {
     pub fn new(evaluator: &'a T, depth: u32) -> Self {
-        MinimaxAgent::<T> { evaluator, depth }
+        MinimaxAgent::<T, G> {
+            evaluator,
+            depth,
+            time: None,
+            tt: RefCell::new(TranspositionTable::new()),
+        }
+    }
+
+    // Searches as deep as it gets in 'time' instead of to a fixed depth.
+    pub fn timed(evaluator: &'a T, time: Duration) -> Self {
+        MinimaxAgent::<T, G> {
+            evaluator,
+            depth: MAX_DEPTH,
+            time: Some(time),
+            tt: RefCell::new(TranspositionTable::new()),
+        }
     }
 }
 
-impl<'a, T, G> Agent<G> for MinimaxAgent<'a, T>
+impl<'a, T, G> Agent<G> for MinimaxAgent<'a, T, G>
 where
     G: Game,
     G::Action: Copy,
     T: Evaluator<G>,
 {
     fn get_action(&self, board: &G, player: Player) -> G::Action {
-        abnegamax_best_action(board, self.depth, self.evaluator, player)
+        self.search(board, player).unwrap().best
+    }
+
+    fn search(&self, board: &G, player: Player) -> Option<SearchResult<G::Action>> {
+        let limits = self
+            .time
+            .map_or_else(SearchLimits::default, SearchLimits::time);
+        let actions: Vec<G::Action> = board.legal_actions().collect();
+        Some(iterative_deepening(
+            board,
+            &actions,
+            self.depth,
+            0,
+            self.evaluator,
+            player,
+            &limits,
+            Some(&mut self.tt.borrow_mut()),
+        ))
     }
 }
 
@@ -58,7 +103,18 @@ where
     T: Evaluator<G>,
 {
     fn get_action(&self, board: &G, player: Player) -> G::Action {
-        batch_abnegamax_best_action(board, self.depth, self.batch_depth, self.evaluator, player)
+        self.search(board, player).unwrap().best
+    }
+
+    fn search(&self, board: &G, player: Player) -> Option<SearchResult<G::Action>> {
+        Some(search_position(
+            board,
+            self.depth,
+            self.batch_depth,
+            self.evaluator,
+            player,
+            &SearchLimits::default(),
+        ))
     }
 }
 
@@ -97,36 +153,39 @@ where
     fn get_action_explored(&self, board: &G, player: Player) -> (G::Action, bool) {
         let mut board = board.clone();
         let mut winning_moves = Vec::new();
-        let mut avs = Vec::new();
+        let mut values = vec![-1. / 0.; G::action_count()];
+        let mut not_losing = 0;
         let actions: Vec<_> = board.legal_actions().collect();
         let mut tt = TranspositionTable::new();
         for action in &actions {
-            board.play_action(*action);
-            let v = -abnegamax(
-                &board,
-                self.depth - 1,
-                self.batch_depth,
-                self.evaluator,
-                !player,
-                Some(&mut tt),
+            let undo = board.play_action(*action);
+            let v = parent_score(
+                abnegamax(
+                    &board,
+                    self.depth - 1,
+                    self.batch_depth,
+                    self.evaluator,
+                    !player,
+                    Some(&mut tt),
+                )
+                .0,
             );
-            board.reverse_last_action(*action);
-            if v == 1. / 0. {
-                winning_moves.push(action);
-            } else if v != -1. / 0. {
-                avs.push((action, v));
+            board.reverse_last_action(undo);
+            if is_win(v) {
+                winning_moves.push((*action, v));
+            } else if !is_loss(v) {
+                let i = G::action_to_index(*action);
+                values[i] = v;
+                not_losing |= 1 << i;
             }
         }
         if winning_moves.len() != 0 {
-            return (
-                *winning_moves[fastrand::usize(0..winning_moves.len())],
-                false,
-            );
-        } else if avs.len() != 0 {
-            let max_av = avs.iter().fold(-1. / 0., |b, (_, v)| v.max(b));
-            let vals = avs.iter().map(|(_, v)| *v).collect();
-            let i = self.policy.choose(&vals);
-            return (*avs[i].0, max_av != avs[i].1);
+            // the fastest win, so that the game can't drag on forever.
+            return (choose_best(&winning_moves), false);
+        } else if not_losing != 0 {
+            let max_av = values.iter().fold(-1. / 0., |b: f64, v| v.max(b));
+            let i = self.policy.choose_masked(&values, not_losing);
+            return (G::index_to_action(i), max_av != values[i]);
         } else {
             return (actions[fastrand::usize(0..actions.len())], false);
         }
@@ -138,6 +197,8 @@ pub struct CompositeAgent<'a, T> {
     depth: u32,
     pub simple_depth: u32, // how deep it should search with SimpleEval.
     pub batch_depth: u32,
+    // Time to think about every move, see with_time.
+    time: Option<Duration>,
 }
 
 impl<'a, T> CompositeAgent<'a, T> {
@@ -147,6 +208,17 @@ impl<'a, T> CompositeAgent<'a, T> {
             depth,
             simple_depth,
             batch_depth,
+            time: None,
+        }
+    }
+
+    // Searches the unclear actions with self.evaluator as deep as it gets in 'time', counting
+    // the search with SimpleEval, instead of to a fixed depth.
+    pub fn with_time(self, time: Duration) -> Self {
+        CompositeAgent::<T> {
+            depth: MAX_DEPTH,
+            time: Some(time),
+            ..self
         }
     }
 }
@@ -163,63 +235,52 @@ where
     // their heuristic value will be computed using self.evaluator at depth self.depth and the action
     // with maximum value will be played.
     fn get_action(&self, board: &G, player: Player) -> G::Action {
-        let mut board = board.clone();
-
-        let mut winning_actions = Vec::new();
-        let mut losing_actions = Vec::new();
-        let mut unclear_actions = Vec::new(); // actions where the search with SimpleEval returned 0.0 (heuristic value or draw).
+        self.search(board, player).unwrap().best
+    }
 
+    fn search(&self, board: &G, player: Player) -> Option<SearchResult<G::Action>> {
+        let limits = self
+            .time
+            .map_or_else(SearchLimits::default, SearchLimits::time);
         let actions: Vec<G::Action> = board.legal_actions().collect();
-        let mut tt = TranspositionTable::new();
-
         let simple_eval = crate::evaluators::SimpleEval::new();
-
-        for action in actions {
-            board.play_action(action);
-            let v = -abnegamax(
-                &board,
-                self.simple_depth - 1,
-                0,
-                &simple_eval,
-                !player,
-                Some(&mut tt),
-            );
-            board.reverse_last_action(action);
-            if v > 0.0 {
-                winning_actions.push((action, v));
-            } else if v < 0.0 {
-                losing_actions.push((action, v));
-            } else {
-                unclear_actions.push(action);
-            }
-        }
-        if !winning_actions.is_empty() {
-            winning_actions.sort_by(|(_, v1), (_, v2)| v2.partial_cmp(v1).unwrap());
-            return winning_actions[0].0;
-        } else if !unclear_actions.is_empty() {
-            let mut tt = TranspositionTable::new();
-            let mut avs = Vec::new();
-            for action in unclear_actions {
-                board.play_action(action);
-                let v = abnegamax(
-                    &board,
-                    self.depth - 1,
-                    self.batch_depth,
-                    self.evaluator,
-                    player,
-                    Some(&mut tt),
-                );
-                board.reverse_last_action(action);
-                avs.push((action, v));
-            }
-            avs.sort_by(|(_, v1), (_, v2)| v2.partial_cmp(v1).unwrap());
-            return avs[0].0;
-        } else if !losing_actions.is_empty() {
-            losing_actions.sort_by(|(_, v1), (_, v2)| v2.partial_cmp(v1).unwrap());
-            return losing_actions[0].0;
-        } else {
-            panic!("no action selected!")
+        let simple = iterative_deepening(
+            board,
+            &actions,
+            self.simple_depth,
+            0,
+            &simple_eval,
+            player,
+            &SearchLimits::default(),
+            None,
+        );
+        // actions where the search with SimpleEval returned 0.0 (heuristic value or draw).
+        let unclear: Vec<_> = simple
+            .root_scores
+            .iter()
+            .filter(|root| root.value == 0.0)
+            .map(|root| root.action)
+            .collect();
+        if simple.value > 0.0 || unclear.is_empty() {
+            return Some(simple);
         }
+        let mut result = iterative_deepening(
+            board,
+            &unclear,
+            self.depth,
+            self.batch_depth,
+            self.evaluator,
+            player,
+            &limits,
+            None,
+        );
+        result.stats.merge(&simple.stats);
+        let losing = simple.root_scores.iter().filter(|root| root.value < 0.0);
+        result.root_scores.extend(losing);
+        result
+            .root_scores
+            .sort_by(|r1, r2| r2.value.total_cmp(&r1.value));
+        Some(result)
     }
 
     // Returns chosen action and a boolean that is true if it was a exploring move
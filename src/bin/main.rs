@@ -7,10 +7,16 @@ extern crate signal_hook;
 
 use clap::{ArgEnum, Parser, Subcommand};
 use gamesolver::agents::{Agent, MinimaxAgent, MinimaxPolicyAgent};
+use gamesolver::evaluators::encoding::{Encoder, Plane};
 use gamesolver::evaluators::{
-    cnn::CNNEval, simple::SimpleEval, Connect4Evaluators, Evaluator, Stack4Evaluators,
+    cnn::CNNEval, simple::SimpleEval, Connect4Evaluators, Evaluator, HexEvaluators, MNKEvaluators,
+    OthelloEvaluators, PopOutEvaluators, Stack4Evaluators,
 };
 use gamesolver::games::connect4::Connect4;
+use gamesolver::games::hex::{Hex11, Hex7};
+use gamesolver::games::mnk::{Gomoku, TicTacToe};
+use gamesolver::games::othello::Othello;
+use gamesolver::games::popout::PopOut;
 use gamesolver::games::stack4::Stack4;
 use gamesolver::games::Game;
 use gamesolver::games::{GameState, Player};
@@ -22,6 +28,7 @@ use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 /*lazy_static! {
     static ref QUIT: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
@@ -41,6 +48,12 @@ struct Cli {
 enum Games {
     Connect4,
     Stack4,
+    TicTacToe,
+    Gomoku,
+    Othello,
+    Hex7,
+    Hex11,
+    PopOut,
 }
 
 #[derive(Subcommand)]
@@ -50,6 +63,11 @@ enum Commands {
 
         /// File containing libtorch model if you want to create for example a CNN evaluator.
         model_file: Option<String>,
+
+        #[clap(long, use_value_delimiter = true)]
+        /// Input planes of the CNN model, for example own,opponent,legal,to-move. Defaults to
+        /// the single plane signed.
+        planes: Vec<Plane>,
     },
     SelfPlay {
         /// AI that is to be trained.
@@ -82,7 +100,14 @@ enum Commands {
         scores: bool,
     },
     /// Lets user play a game against the AI.
-    Play { ai_file: String },
+    Play {
+        ai_file: String,
+
+        #[clap(short, long, parse(try_from_str = parse_seconds))]
+        /// Seconds the AI thinks about every move, searching deeper until the time is up,
+        /// instead of searching to a fixed depth.
+        time: Option<Duration>,
+    },
     Compare {
         ai_file1: String,
         ai_file2: String,
@@ -91,12 +116,32 @@ enum Commands {
         #[clap(default_value_t = 4)]
         depth: u32,
     },
+    /// Counts the positions reached after depth moves to check move generation.
+    Perft {
+        depth: u32,
+
+        #[clap(short, long)]
+        /// Print the count after every first move.
+        divide: bool,
+    },
+}
+
+// Reads a number of seconds like 1.5, which has to be neither negative nor too large for a
+// Duration.
+fn parse_seconds(arg: &str) -> Result<Duration, String> {
+    let seconds: f64 = arg.parse().map_err(|e| format!("{}", e))?;
+    Duration::try_from_secs_f64(seconds).map_err(|e| format!("{}", e))
 }
 
 impl Commands {
-    fn create(ai_file: String, model_file: Option<String>) {
+    fn create(ai_file: String, model_file: Option<String>, planes: Vec<Plane>) {
         if let Some(model_file) = model_file {
-            let evaluator = Connect4Evaluators::CNN(CNNEval::new(model_file));
+            let encoder = if planes.is_empty() {
+                Encoder::default()
+            } else {
+                Encoder::new(planes)
+            };
+            let evaluator = Connect4Evaluators::CNN(CNNEval::with_encoder(model_file, encoder));
             let policy = EpsilonGreedy::new(0.1);
             let mut ai = QLearning::new(evaluator, Box::new(policy), 0.0001);
             ai.discount = 0.95;
@@ -234,6 +279,20 @@ impl Commands {
         mm.play_n_games(nb_games);
         println!("{:?}", mm.scores());
     }
+    fn perft<G: Game>(depth: u32, divide: bool) {
+        let mut board = G::new();
+        let start = Instant::now();
+        let count = if divide {
+            let counts = gamesolver::perft::divide(&mut board, depth);
+            for (action, count) in &counts {
+                println!("{:?}: {}", action, count);
+            }
+            counts.iter().map(|(_, count)| count).sum()
+        } else {
+            gamesolver::perft::perft(&mut board, depth)
+        };
+        println!("{} positions in {:.2?}", count, start.elapsed());
+    }
 }
 
 fn run_command<G, E>(command: Commands)
@@ -245,8 +304,9 @@ where
         Commands::Create {
             ai_file,
             model_file,
+            planes,
         } => {
-            Commands::create(ai_file, model_file);
+            Commands::create(ai_file, model_file, planes);
         }
         Commands::SelfPlay {
             ai_file,
@@ -265,13 +325,18 @@ where
         } => {
             Commands::train_against::<G, E>(ai_file, opponent_file, iterations, progress, scores);
         }
-        Commands::Play { ai_file } => {
+        Commands::Play { ai_file, time } => {
             let ai: QLearning<E> =
                 serde_json::from_str(&std::fs::read_to_string(&ai_file).expect("valid file"))
                     .expect("json of RL");
-            let mut agenta = MinimaxPolicyAgent::new(ai.get_evaluator(), ai.get_policy(), 3);
-            agenta.batch_depth = 2;
-            user_vs_agent(&agenta);
+            if let Some(time) = time {
+                let agent = MinimaxAgent::timed(ai.get_evaluator(), time);
+                user_vs_agent(&agent);
+            } else {
+                let mut agenta = MinimaxPolicyAgent::new(ai.get_evaluator(), ai.get_policy(), 3);
+                agenta.batch_depth = 2;
+                user_vs_agent(&agenta);
+            }
         }
         Commands::Compare {
             ai_file1,
@@ -281,6 +346,9 @@ where
         } => {
             Commands::compare::<G, E>(ai_file1, ai_file2, nb_games, depth);
         }
+        Commands::Perft { depth, divide } => {
+            Commands::perft::<G>(depth, divide);
+        }
     }
 }
 
@@ -308,6 +376,24 @@ fn main() {
         Games::Stack4 => {
             run_command::<Stack4, Stack4Evaluators>(args.command);
         }
+        Games::TicTacToe => {
+            run_command::<TicTacToe, MNKEvaluators>(args.command);
+        }
+        Games::Gomoku => {
+            run_command::<Gomoku, MNKEvaluators>(args.command);
+        }
+        Games::Othello => {
+            run_command::<Othello, OthelloEvaluators>(args.command);
+        }
+        Games::Hex7 => {
+            run_command::<Hex7, HexEvaluators>(args.command);
+        }
+        Games::Hex11 => {
+            run_command::<Hex11, HexEvaluators>(args.command);
+        }
+        Games::PopOut => {
+            run_command::<PopOut, PopOutEvaluators>(args.command);
+        }
     }
 }
 
@@ -8,7 +8,7 @@ extern crate serde_json;
 
 use actix_files::Files;
 use actix_web::middleware::Logger;
-use actix_web::{get, web, App, HttpServer, Responder};
+use actix_web::{error, get, web, App, HttpServer, Responder};
 use lazy_static::lazy_static;
 use num_traits::FromPrimitive;
 
@@ -17,13 +17,18 @@ use gamesolver::evaluators::CNNEval;
 use gamesolver::evaluators::Stack4Evaluators;
 use gamesolver::games::stack4::Stack4;
 use gamesolver::games::Game;
-use gamesolver::games::Player;
+use gamesolver::games::{GameState, Player};
 use gamesolver::qlearning::QLearning;
+use gamesolver::search::{SearchResult, MAX_DEPTH};
 use serde::{Deserialize, Serialize};
 use std::fs;
+use std::time::Duration;
 
 static AI_PATH: &str = "badcnn.json";
 
+// How long the AI thinks about every move.
+const THINKING_TIME: Duration = Duration::from_secs(2);
+
 #[derive(Serialize, Deserialize)]
 struct MoveRequest {
     board: Vec<u8>,
@@ -35,6 +40,23 @@ struct Move {
     x: usize,
     y: usize,
     player: u8,
+    // Squares [x, y] where red and yellow would get four in a row after the move.
+    red_threats: Vec<[usize; 2]>,
+    yellow_threats: Vec<[usize; 2]>,
+    // Why the move was picked: its score, like "win in 3" or "+0.250", how deep the search
+    // went and the moves it expects to follow.
+    score: String,
+    depth: u32,
+    pv: Vec<[usize; 2]>,
+}
+
+fn threat_squares(board: &Stack4, player: Player) -> Vec<[usize; 2]> {
+    let squares = board.threats().squares(player);
+    let [w, h] = Stack4::shape();
+    (0..w * h)
+        .filter(|i| squares >> i & 1 == 1)
+        .map(|i| [i % w, i / w])
+        .collect()
 }
 
 lazy_static! {
@@ -45,10 +67,11 @@ lazy_static! {
     };
 }
 
-fn calc_move(board: &Stack4, player: Player) -> <Stack4 as Game>::Action {
+fn calc_move(board: &Stack4, player: Player) -> SearchResult<<Stack4 as Game>::Action> {
     //let agent = MinimaxAgent::<Stack4Evaluators>::new(&EVALUATOR, 5);
-    let agent = CompositeAgent::<Stack4Evaluators>::new(&EVALUATOR, 4, 0, 6);
-    agent.get_action(board, player)
+    let agent = CompositeAgent::<Stack4Evaluators>::new(&EVALUATOR, MAX_DEPTH, 0, 6)
+        .with_time(THINKING_TIME);
+    agent.search(board, player).unwrap()
 }
 
 #[get("/{name}/index.html")]
@@ -57,28 +80,29 @@ async fn index(path: web::Path<String>) -> impl Responder {
     format!("Hello {}!", name)
 }
 
-async fn request_move(info: web::Json<MoveRequest>) -> web::Json<Move> {
-    println!("post!");
-
-    let mut board = Stack4::new();
-    for (i, &player) in info.board.iter().enumerate() {
-        let row = i % 8;
-        let col = i / 8;
-        if player == 0 {
-            board.set(col, row, player);
-        } else {
-            board.set(col, row, player);
-        }
+async fn request_move(info: web::Json<MoveRequest>) -> Result<web::Json<Move>, actix_web::Error> {
+    let player = FromPrimitive::from_u8(info.player_to_move)
+        .ok_or_else(|| error::ErrorBadRequest("player_to_move must be 1 or 2"))?;
+    let board = Stack4::from_cells(&info.board, Some(player)).map_err(error::ErrorBadRequest)?;
+    if board.game_state() != GameState::InProgress {
+        return Err(error::ErrorBadRequest("the game is already over"));
     }
-    let player = FromPrimitive::from_u8(info.player_to_move).expect("an u8");
-    let (x, y) = calc_move(&board, player);
-    println!("{:?}", board);
-    println!("{:?}", (x, y));
-    web::Json(Move {
+    let result = calc_move(&board, player);
+    let (x, y) = result.best;
+    log::debug!("{:?}", board);
+    log::debug!("{:#}", result);
+    let mut board = board;
+    board.play_action((x, y));
+    Ok(web::Json(Move {
         x,
         y,
         player: info.player_to_move,
-    })
+        red_threats: threat_squares(&board, Player::Red),
+        yellow_threats: threat_squares(&board, Player::Yellow),
+        score: result.score(),
+        depth: result.depth,
+        pv: result.pv.iter().map(|&(x, y)| [x, y]).collect(),
+    }))
 }
 
 #[actix_web::main]
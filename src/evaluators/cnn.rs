@@ -1,28 +1,66 @@
+use super::encoding::Encoder;
 use super::Evaluator;
 use crate::games::{Game, GameState, Player};
 use anyhow::Result;
-use serde::de::{SeqAccess, Visitor};
+use serde::de::{IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::ser::SerializeStruct;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
 use tch::nn::{ModuleT, VarStore};
 use tch::Device;
 use tch::TrainableCModule;
 
+// The model gets a [N, C, W, H] tensor where C is the number of planes of the encoder.
 pub struct CNNEval {
     pub model: TrainableCModule,
     pub vs: VarStore,
+    pub encoder: Encoder,
 }
 
 impl CNNEval {
+    // A model that takes the single plane of Game::vectorize.
     pub fn new(model_path: String) -> Self {
+        CNNEval::with_encoder(model_path, Encoder::default())
+    }
+
+    pub fn with_encoder(model_path: String, encoder: Encoder) -> Self {
         let device = Device::Cpu;
         let vs = VarStore::new(device);
         let model = TrainableCModule::load(&model_path, vs.root()).unwrap();
-        CNNEval { model, vs }
+        CNNEval { model, vs, encoder }
     }
     fn tmp_file_name(len: usize) -> String {
         (0..len).map(|_| fastrand::alphanumeric()).collect()
     }
+    fn load_bytes(data: &[u8], encoder: Encoder) -> Self {
+        let device = Device::Cpu;
+        let vs = VarStore::new(device);
+        let fname = CNNEval::tmp_file_name(10);
+        std::fs::write(&fname, data).expect(&format!("failed to write to {}", &fname));
+        let model = TrainableCModule::load(&fname, vs.root())
+            .expect(&format!("couldn't load module from file {}", &fname));
+        std::fs::remove_file(&fname).unwrap();
+        CNNEval { model, vs, encoder }
+    }
+}
+
+// The [N, C, W, H] input for 'boards', every plane is stored row after row of shape()[1] values
+// like the encoder writes it.
+fn input_tensor<G: Game>(encoder: &Encoder, boards: &[G], player: Player) -> tch::Tensor {
+    let shape = G::shape();
+    let planes = encoder.nb_planes();
+    let mut encoded = Vec::with_capacity(planes * shape[0] * shape[1] * boards.len());
+    for board in boards {
+        encoder.encode_into(board, player, &mut encoded);
+    }
+    let mut tensor = tch::Tensor::of_slice(&encoded);
+    let _ = tensor.resize_(&[
+        boards.len() as i64,
+        planes as i64,
+        shape[0] as i64,
+        shape[1] as i64,
+    ]);
+    tensor
 }
 
 impl<G> Evaluator<G> for CNNEval
@@ -40,19 +78,7 @@ where
             }
             GameState::Draw => 0.0,
             GameState::InProgress => {
-                let vectorized_board = board.vectorize(player);
-                let shape = G::shape();
-                let tensor = unsafe {
-                    let ptr = vectorized_board.as_ptr();
-                    let t = tch::Tensor::of_blob(
-                        ptr as *const u8,
-                        &[1, 1, shape[0] as i64, shape[1] as i64],
-                        &[0, 0, shape[0] as i64, 1],
-                        tch::Kind::Double,
-                        tch::Device::Cpu,
-                    );
-                    t
-                };
+                let tensor = input_tensor(&self.encoder, std::slice::from_ref(board), player);
                 let v = self.model.forward_t(&tensor, true);
                 let data_ptr = v.data_ptr();
                 unsafe { *(data_ptr as *const f64) }
@@ -60,14 +86,7 @@ where
         }
     }
     fn values(&self, boards: &Vec<G>, player: Player) -> Vec<f64> {
-        let mut vectorized_boards: Vec<f64> = Vec::with_capacity(64 * boards.len());
-        for board in boards {
-            vectorized_boards.append(&mut board.vectorize(player));
-        }
-        let mut tensor = tch::Tensor::of_slice(&vectorized_boards);
-        let shape = G::shape();
-
-        let _ = tensor.resize_(&[boards.len() as i64, 1, shape[0] as i64, shape[1] as i64]);
+        let tensor = input_tensor(&self.encoder, boards, player);
         let v = self.model.forward_t(&tensor, true);
         let out: Vec<f64> = Vec::from(v);
         out
@@ -77,9 +96,7 @@ where
         for var in self.vs.trainable_variables().iter_mut() {
             var.zero_grad();
         }
-        let mut tboard = tch::Tensor::of_slice(&board.vectorize(player));
-        let shape = G::shape();
-        let _ = tboard.resize_(&[1, 1, shape[0] as i64, shape[1] as i64]);
+        let tboard = input_tensor(&self.encoder, std::slice::from_ref(board), player);
         let _out = self.model.forward_t(&tboard, true);
         _out.backward();
 
@@ -119,16 +136,21 @@ impl Serialize for CNNEval {
         self.model.save(&fname).unwrap();
         let v = std::fs::read(&fname).expect(&format!("failed to read {}", &fname));
         std::fs::remove_file(&fname).unwrap();
-        serializer.serialize_bytes(&v)
+        let mut state = serializer.serialize_struct("CNNEval", 2)?;
+        state.serialize_field("encoder", &self.encoder)?;
+        state.serialize_field("model", &v)?;
+        state.end()
     }
 }
 
+// Models saved before there were encoders are just the bytes of the model and get the
+// default encoder.
 impl<'de> Deserialize<'de> for CNNEval {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        deserializer.deserialize_seq(ByteDataVisitor)
+        deserializer.deserialize_any(ByteDataVisitor)
     }
 }
 
@@ -137,24 +159,66 @@ impl<'de> Visitor<'de> for ByteDataVisitor {
     type Value = CNNEval;
 
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        formatter.write_str("bytes of data")
+        formatter.write_str("bytes of data or a map with an encoder and a model")
     }
 
     fn visit_seq<S>(self, mut access: S) -> Result<Self::Value, S::Error>
     where
         S: SeqAccess<'de>,
     {
-        let device = Device::Cpu;
-        let vs = VarStore::new(device);
-        let fname = CNNEval::tmp_file_name(10);
         let mut data = Vec::new();
         while let Some(value) = access.next_element()? {
             data.push(value);
         }
-        std::fs::write(&fname, &data).expect(&format!("failed to write to {}", &fname));
-        let model = TrainableCModule::load(&fname, vs.root())
-            .expect(&format!("couldn't load module from file {}", &fname));
-        std::fs::remove_file(&fname).unwrap();
-        Ok(CNNEval { model, vs })
+        Ok(CNNEval::load_bytes(&data, Encoder::default()))
+    }
+
+    fn visit_map<M>(self, mut access: M) -> Result<Self::Value, M::Error>
+    where
+        M: MapAccess<'de>,
+    {
+        let mut encoder = None;
+        let mut data: Option<Vec<u8>> = None;
+        while let Some(key) = access.next_key::<String>()? {
+            match key.as_str() {
+                "encoder" => encoder = Some(access.next_value()?),
+                "model" => data = Some(access.next_value()?),
+                _ => {
+                    access.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        let data = data.ok_or_else(|| serde::de::Error::missing_field("model"))?;
+        Ok(CNNEval::load_bytes(&data, encoder.unwrap_or_default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::input_tensor;
+    use crate::evaluators::encoding::Encoder;
+    use crate::games::connect4::Connect4;
+    use crate::games::{Game, Player};
+
+    #[test]
+    fn value_and_values_agree() {
+        // a board that isn't square, so mixing up the two sides shows.
+        let mut board = Connect4::new();
+        for action in [3, 3, 4, 0, 6] {
+            board.play_action(action);
+        }
+        let encoder = Encoder::default();
+        let [w, h] = Connect4::shape();
+        let encoded = encoder.encode(&board, Player::Yellow);
+        let single = input_tensor(&encoder, &[board], Player::Yellow);
+        let batch = input_tensor(&encoder, &[Connect4::new(), board], Player::Yellow);
+        for x in 0..w {
+            for y in 0..h {
+                let index = [0, 0, x as i64, y as i64];
+                let expected = encoded[x * h + y];
+                assert_eq!(single.double_value(&index), expected);
+                assert_eq!(batch.double_value(&[1, 0, x as i64, y as i64]), expected);
+            }
+        }
     }
 }
@@ -1,6 +1,7 @@
 use super::Evaluator;
 use crate::games::connect4::{Connect4, BOARD_HEIGHT, BOARD_WIDTH};
 use crate::games::stack4::Stack4;
+use crate::games::threats::Threats;
 use crate::games::{GameState, Player};
 use serde::{Deserialize, Serialize};
 
@@ -34,6 +35,23 @@ fn pieces_in_row_stack4(board: &Stack4, pos: [usize; 2], dir: [i32; 2], player:
     k as u32 - 1
 }
 
+// Threats of 'player' and then of the opponent: how many are on rows where the player can
+// hope to play them out, odd rows for red and even for yellow when 'parity' is set and every
+// threat otherwise, and if they have a double threat.
+fn threat_features(threats: &Threats, player: Player, parity: bool) -> [f64; 4] {
+    let useful = |p: Player| match p {
+        _ if !parity => threats.squares(p),
+        Player::Red => threats.odd(p),
+        Player::Yellow => threats.even(p),
+    };
+    [
+        useful(player).count_ones() as f64,
+        useful(!player).count_ones() as f64,
+        threats.double(player) as u8 as f64,
+        threats.double(!player) as u8 as f64,
+    ]
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct ConsequtiveEval {
     pub params: Vec<f64>,
@@ -110,7 +128,10 @@ impl Evaluator<Stack4> for ConsequtiveEval {
 impl ConsequtiveEval {
     pub fn new() -> Self {
         ConsequtiveEval {
-            params: vec![0.0, 0.0, 0.0, 0.0, 0.0, 0.0], // [v for 1 in a row,  v for 2 in a row, v for 3 in a row,    for opponent]
+            // [v for 1 in a row,  v for 2 in a row, v for 3 in a row,    for opponent,
+            //  v for useful threats, for opponent, v for a double threat, for opponent]
+            // Models saved with only the first six ignore the threat features.
+            params: vec![0.0; 10],
         }
     }
 
@@ -150,6 +171,7 @@ impl ConsequtiveEval {
         let mx = 10.0;
         f.iter()
             .map(|x| mx * (1.0 - (-x as f64 / mx).exp()))
+            .chain(threat_features(&board.threats(), player, true))
             .collect()
     }
     fn features_stack4(&self, board: &Stack4, player: Player) -> Vec<f64> {
@@ -188,6 +210,35 @@ impl ConsequtiveEval {
         let mx = 10.0;
         f.iter()
             .map(|x| mx * (1.0 - (-x as f64 / mx).exp()))
+            .chain(threat_features(&board.threats(), player, false))
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ConsequtiveEval;
+    use crate::games::connect4::Connect4;
+    use crate::games::{Game, Player};
+
+    #[test]
+    fn threat_features() {
+        // red threatens the first row and yellow the second, both on rows that suit them.
+        let mut board = Connect4::new();
+        for action in [0, 0, 1, 1, 2, 2] {
+            board.play_action(action);
+        }
+        let eval = ConsequtiveEval::new();
+        let features = eval.features(&board, Player::Red);
+        assert_eq!(features.len(), eval.params.len());
+        assert_eq!(features[6..], [1.0, 1.0, 0.0, 0.0]);
+
+        // red has three in the middle of the first row with both ends open.
+        let mut board = Connect4::new();
+        for action in [1, 1, 2, 2, 3] {
+            board.play_action(action);
+        }
+        let features = eval.features(&board, Player::Yellow);
+        assert_eq!(features[6..], [0.0, 2.0, 0.0, 1.0]);
+    }
+}
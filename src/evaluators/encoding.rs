@@ -0,0 +1,174 @@
+use crate::games::{Game, Player};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+// One input plane of a neural network. A plane has a value for every square of the board in
+// the same order as Game::vectorize, 'player' is the player the position is evaluated for.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Plane {
+    // 1 for the player's pieces, -1 for the opponent's and 0 for empty squares. This is what
+    // Game::vectorize gives and the only plane of older models.
+    Signed,
+    Own,
+    Opponent,
+    Empty,
+    // Squares that a legal action puts a piece on, see Game::legal_squares.
+    Legal,
+    // 1 everywhere if the player is the one to move, otherwise 0.
+    ToMove,
+    // Empty squares that would give the player a line, see Game::threat_squares.
+    OwnThreats,
+    OpponentThreats,
+}
+
+impl FromStr for Plane {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "signed" => Ok(Plane::Signed),
+            "own" => Ok(Plane::Own),
+            "opponent" => Ok(Plane::Opponent),
+            "empty" => Ok(Plane::Empty),
+            "legal" => Ok(Plane::Legal),
+            "to-move" => Ok(Plane::ToMove),
+            "own-threats" => Ok(Plane::OwnThreats),
+            "opponent-threats" => Ok(Plane::OpponentThreats),
+            _ => Err(format!("unknown plane '{}'", s)),
+        }
+    }
+}
+
+// Turns positions into neural network inputs with one plane after another. It is saved with
+// the model so that a model is always given the planes it was trained with.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Encoder {
+    pub planes: Vec<Plane>,
+}
+
+impl Encoder {
+    pub fn new(planes: Vec<Plane>) -> Self {
+        assert!(!planes.is_empty(), "an encoder needs at least one plane");
+        Encoder { planes }
+    }
+
+    pub fn nb_planes(&self) -> usize {
+        self.planes.len()
+    }
+
+    pub fn encode<G: Game>(&self, board: &G, player: Player) -> Vec<f64> {
+        let [w, h] = G::shape();
+        let mut v = Vec::with_capacity(self.planes.len() * w * h);
+        self.encode_into(board, player, &mut v);
+        v
+    }
+
+    // Appends the planes of 'board' to 'v', so that a batch can be encoded into one buffer.
+    pub fn encode_into<G: Game>(&self, board: &G, player: Player, v: &mut Vec<f64>) {
+        let signed = board.vectorize(player);
+        for plane in &self.planes {
+            match plane {
+                Plane::Signed => v.extend_from_slice(&signed),
+                Plane::Own => v.extend(signed.iter().map(|&s| (s > 0.0) as u8 as f64)),
+                Plane::Opponent => v.extend(signed.iter().map(|&s| (s < 0.0) as u8 as f64)),
+                Plane::Empty => v.extend(signed.iter().map(|&s| (s == 0.0) as u8 as f64)),
+                Plane::Legal => push_squares(board.legal_squares(), signed.len(), v),
+                Plane::ToMove => {
+                    let to_move = (board.cur_player() == player) as u8 as f64;
+                    v.resize(v.len() + signed.len(), to_move);
+                }
+                Plane::OwnThreats => push_squares(board.threat_squares(player), signed.len(), v),
+                Plane::OpponentThreats => {
+                    push_squares(board.threat_squares(!player), signed.len(), v)
+                }
+            }
+        }
+    }
+}
+
+// A single Signed plane, which is how models were fed before there were encoders.
+impl Default for Encoder {
+    fn default() -> Self {
+        Encoder::new(vec![Plane::Signed])
+    }
+}
+
+fn push_squares(squares: u128, nb_squares: usize, v: &mut Vec<f64>) {
+    v.extend((0..nb_squares).map(|i| (squares >> i & 1) as f64));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::games::connect4::Connect4;
+    use crate::games::stack4::Stack4;
+
+    #[test]
+    fn connect4_planes() {
+        let mut board = Connect4::new();
+        for action in [3, 3, 4, 4, 5] {
+            board.play_action(action);
+        }
+        let encoder = Encoder::new(vec![
+            Plane::Own,
+            Plane::Opponent,
+            Plane::Empty,
+            Plane::Legal,
+            Plane::ToMove,
+            Plane::OwnThreats,
+            Plane::OpponentThreats,
+        ]);
+        let v = encoder.encode(&board, Player::Red);
+        assert_eq!(v.len(), 7 * 42);
+        let plane = |i: usize| &v[i * 42..(i + 1) * 42];
+        let square = |x: usize, y: usize| x + 7 * y;
+
+        assert_eq!(plane(0).iter().sum::<f64>(), 3.0);
+        assert_eq!(plane(0)[square(5, 0)], 1.0);
+        assert_eq!(plane(1)[square(4, 1)], 1.0);
+        assert_eq!(plane(2).iter().sum::<f64>(), 37.0);
+        for x in 0..7 {
+            let y = match x {
+                3 | 4 => 2,
+                5 => 1,
+                _ => 0,
+            };
+            assert_eq!(plane(3)[square(x, y)], 1.0);
+        }
+        assert_eq!(plane(3).iter().sum::<f64>(), 7.0);
+        // yellow is to move.
+        assert!(plane(4).iter().all(|&t| t == 0.0));
+        // red threatens to get four on both sides of the three in a row.
+        assert_eq!(plane(5).iter().sum::<f64>(), 2.0);
+        assert_eq!(plane(5)[square(2, 0)], 1.0);
+        assert_eq!(plane(5)[square(6, 0)], 1.0);
+        assert!(plane(6).iter().all(|&t| t == 0.0));
+
+        let signed = Encoder::default().encode(&board, Player::Yellow);
+        assert_eq!(signed, board.vectorize(Player::Yellow));
+    }
+
+    #[test]
+    fn stack4_threats() {
+        let mut board = Stack4::new();
+        for action in [(0, 0), (7, 7), (0, 1), (7, 6), (0, 2)] {
+            board.play_action(action);
+        }
+        let v = Encoder::new(vec![Plane::OpponentThreats, Plane::ToMove])
+            .encode(&board, Player::Yellow);
+        assert_eq!(v[..64].iter().sum::<f64>(), 1.0);
+        assert_eq!(v[3 * 8], 1.0);
+        assert!(v[64..].iter().all(|&t| t == 1.0));
+    }
+
+    #[test]
+    fn parse_and_serialize() {
+        let planes: Result<Vec<Plane>, _> =
+            "own,opponent,to-move".split(',').map(str::parse).collect();
+        let encoder = Encoder::new(planes.unwrap());
+        assert_eq!(encoder.planes, [Plane::Own, Plane::Opponent, Plane::ToMove]);
+        assert!("threats".parse::<Plane>().is_err());
+        let json = serde_json::to_string(&encoder).unwrap();
+        assert_eq!(serde_json::from_str::<Encoder>(&json).unwrap(), encoder);
+    }
+}
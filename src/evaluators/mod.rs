@@ -1,5 +1,6 @@
 pub mod cnn;
 pub mod consequtive;
+pub mod encoding;
 pub mod lines;
 pub mod simple;
 
@@ -10,6 +11,10 @@ use serde::{Deserialize, Serialize};
 pub use simple::SimpleEval;
 
 use crate::games::connect4::Connect4;
+use crate::games::hex::Hex;
+use crate::games::mnk::MNK;
+use crate::games::othello::Othello;
+use crate::games::popout::PopOut;
 use crate::games::stack4::Stack4;
 use crate::games::{Game, Player};
 
@@ -51,6 +56,30 @@ pub enum Connect4Evaluators {
     Consequtive(ConsequtiveEval),
 }
 
+#[derive(Serialize, Deserialize)]
+pub enum MNKEvaluators {
+    Simple(SimpleEval),
+    CNN(CNNEval),
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum PopOutEvaluators {
+    Simple(SimpleEval),
+    CNN(CNNEval),
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum HexEvaluators {
+    Simple(SimpleEval),
+    CNN(CNNEval),
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum OthelloEvaluators {
+    Simple(SimpleEval),
+    CNN(CNNEval),
+}
+
 impl Evaluator<Connect4> for Connect4Evaluators {
     fn value(&self, board: &Connect4, player: Player) -> f64 {
         match self {
@@ -155,3 +184,157 @@ impl Evaluator<Stack4> for Stack4Evaluators {
         }
     }
 }
+
+impl<const M: usize, const N: usize, const K: usize> Evaluator<MNK<M, N, K>> for MNKEvaluators {
+    fn value(&self, board: &MNK<M, N, K>, player: Player) -> f64 {
+        match self {
+            MNKEvaluators::Simple(ref eval) => eval.value(board, player),
+            MNKEvaluators::CNN(ref eval) => eval.value(board, player),
+        }
+    }
+    fn values(&self, boards: &Vec<MNK<M, N, K>>, player: Player) -> Vec<f64> {
+        match self {
+            MNKEvaluators::Simple(ref eval) => eval.values(boards, player),
+            MNKEvaluators::CNN(ref eval) => eval.values(boards, player),
+        }
+    }
+    fn gradient(&self, board: &MNK<M, N, K>, player: Player) -> Vec<f64> {
+        match self {
+            MNKEvaluators::Simple(ref eval) => eval.gradient(board, player),
+            MNKEvaluators::CNN(ref eval) => eval.gradient(board, player),
+        }
+    }
+    fn apply_update(&mut self, update: &[f64]) {
+        match self {
+            MNKEvaluators::Simple(ref mut eval) => {
+                <SimpleEval as Evaluator<MNK<M, N, K>>>::apply_update(eval, update)
+            }
+            MNKEvaluators::CNN(ref mut eval) => {
+                <CNNEval as Evaluator<MNK<M, N, K>>>::apply_update(eval, update)
+            }
+        }
+    }
+    fn get_params(&self) -> Vec<f64> {
+        match self {
+            MNKEvaluators::Simple(ref eval) => {
+                <SimpleEval as Evaluator<MNK<M, N, K>>>::get_params(eval)
+            }
+            MNKEvaluators::CNN(ref eval) => <CNNEval as Evaluator<MNK<M, N, K>>>::get_params(eval),
+        }
+    }
+}
+
+impl Evaluator<Othello> for OthelloEvaluators {
+    fn value(&self, board: &Othello, player: Player) -> f64 {
+        match self {
+            OthelloEvaluators::Simple(ref eval) => eval.value(board, player),
+            OthelloEvaluators::CNN(ref eval) => eval.value(board, player),
+        }
+    }
+    fn values(&self, boards: &Vec<Othello>, player: Player) -> Vec<f64> {
+        match self {
+            OthelloEvaluators::Simple(ref eval) => eval.values(boards, player),
+            OthelloEvaluators::CNN(ref eval) => eval.values(boards, player),
+        }
+    }
+    fn gradient(&self, board: &Othello, player: Player) -> Vec<f64> {
+        match self {
+            OthelloEvaluators::Simple(ref eval) => eval.gradient(board, player),
+            OthelloEvaluators::CNN(ref eval) => eval.gradient(board, player),
+        }
+    }
+    fn apply_update(&mut self, update: &[f64]) {
+        match self {
+            OthelloEvaluators::Simple(ref mut eval) => {
+                <SimpleEval as Evaluator<Othello>>::apply_update(eval, update)
+            }
+            OthelloEvaluators::CNN(ref mut eval) => {
+                <CNNEval as Evaluator<Othello>>::apply_update(eval, update)
+            }
+        }
+    }
+    fn get_params(&self) -> Vec<f64> {
+        match self {
+            OthelloEvaluators::Simple(ref eval) => {
+                <SimpleEval as Evaluator<Othello>>::get_params(eval)
+            }
+            OthelloEvaluators::CNN(ref eval) => <CNNEval as Evaluator<Othello>>::get_params(eval),
+        }
+    }
+}
+
+impl<const N: usize> Evaluator<Hex<N>> for HexEvaluators {
+    fn value(&self, board: &Hex<N>, player: Player) -> f64 {
+        match self {
+            HexEvaluators::Simple(ref eval) => eval.value(board, player),
+            HexEvaluators::CNN(ref eval) => eval.value(board, player),
+        }
+    }
+    fn values(&self, boards: &Vec<Hex<N>>, player: Player) -> Vec<f64> {
+        match self {
+            HexEvaluators::Simple(ref eval) => eval.values(boards, player),
+            HexEvaluators::CNN(ref eval) => eval.values(boards, player),
+        }
+    }
+    fn gradient(&self, board: &Hex<N>, player: Player) -> Vec<f64> {
+        match self {
+            HexEvaluators::Simple(ref eval) => eval.gradient(board, player),
+            HexEvaluators::CNN(ref eval) => eval.gradient(board, player),
+        }
+    }
+    fn apply_update(&mut self, update: &[f64]) {
+        match self {
+            HexEvaluators::Simple(ref mut eval) => {
+                <SimpleEval as Evaluator<Hex<N>>>::apply_update(eval, update)
+            }
+            HexEvaluators::CNN(ref mut eval) => {
+                <CNNEval as Evaluator<Hex<N>>>::apply_update(eval, update)
+            }
+        }
+    }
+    fn get_params(&self) -> Vec<f64> {
+        match self {
+            HexEvaluators::Simple(ref eval) => <SimpleEval as Evaluator<Hex<N>>>::get_params(eval),
+            HexEvaluators::CNN(ref eval) => <CNNEval as Evaluator<Hex<N>>>::get_params(eval),
+        }
+    }
+}
+
+impl Evaluator<PopOut> for PopOutEvaluators {
+    fn value(&self, board: &PopOut, player: Player) -> f64 {
+        match self {
+            PopOutEvaluators::Simple(ref eval) => eval.value(board, player),
+            PopOutEvaluators::CNN(ref eval) => eval.value(board, player),
+        }
+    }
+    fn values(&self, boards: &Vec<PopOut>, player: Player) -> Vec<f64> {
+        match self {
+            PopOutEvaluators::Simple(ref eval) => eval.values(boards, player),
+            PopOutEvaluators::CNN(ref eval) => eval.values(boards, player),
+        }
+    }
+    fn gradient(&self, board: &PopOut, player: Player) -> Vec<f64> {
+        match self {
+            PopOutEvaluators::Simple(ref eval) => eval.gradient(board, player),
+            PopOutEvaluators::CNN(ref eval) => eval.gradient(board, player),
+        }
+    }
+    fn apply_update(&mut self, update: &[f64]) {
+        match self {
+            PopOutEvaluators::Simple(ref mut eval) => {
+                <SimpleEval as Evaluator<PopOut>>::apply_update(eval, update)
+            }
+            PopOutEvaluators::CNN(ref mut eval) => {
+                <CNNEval as Evaluator<PopOut>>::apply_update(eval, update)
+            }
+        }
+    }
+    fn get_params(&self) -> Vec<f64> {
+        match self {
+            PopOutEvaluators::Simple(ref eval) => {
+                <SimpleEval as Evaluator<PopOut>>::get_params(eval)
+            }
+            PopOutEvaluators::CNN(ref eval) => <CNNEval as Evaluator<PopOut>>::get_params(eval),
+        }
+    }
+}
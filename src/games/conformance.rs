@@ -0,0 +1,173 @@
+use crate::games::{Game, GameState, MoveList};
+use std::collections::HashMap;
+
+// Checks that a Game implementation is consistent with itself. Meant to be called from the
+// tests of every game, it panics with a description of the first problem it finds.
+//
+// Plays 'nb_playouts' random games and at every position checks that
+// - every symmetry has the same game state,
+// - vectorize gives shape()[0] * shape()[1] values,
+// - there are legal actions exactly when the game is in progress, and legal_mask and
+//   generate_moves agree with legal_actions,
+// - positions with the same uid are the same board and the same board always has the same uid.
+// Then all moves are reversed and the positions on the way back must match the ones seen on
+// the way forward, ending with the starting position.
+pub fn check_game<G: Game>(nb_playouts: usize, seed: u64) {
+    let rng = fastrand::Rng::with_seed(seed);
+    let mut boards = HashMap::new();
+    let mut uids = HashMap::new();
+    for _ in 0..nb_playouts {
+        let mut board = G::new();
+        let mut history = Vec::new();
+        loop {
+            check_position(&board);
+            check_uid(&board, &mut boards, &mut uids);
+            history.push(Snapshot::new(&board));
+            if board.game_state() != GameState::InProgress {
+                break;
+            }
+            let actions: Vec<_> = board.legal_actions().collect();
+            let action = actions[rng.usize(0..actions.len())];
+            let undo = board.play_action(action);
+            history.last_mut().unwrap().undo = Some(undo);
+        }
+        history.pop();
+        while let Some(snapshot) = history.pop() {
+            board.reverse_last_action(snapshot.undo.unwrap());
+            snapshot.check(&board);
+        }
+    }
+}
+
+// What is compared when a move is reversed.
+struct Snapshot<G: Game> {
+    uid: G::Uid,
+    key: u64,
+    game_state: GameState,
+    length: u32,
+    board: String,
+    // How to get back to this position from the next one.
+    undo: Option<G::Undo>,
+}
+
+impl<G: Game> Snapshot<G> {
+    fn new(board: &G) -> Self {
+        Snapshot {
+            uid: board.uid(),
+            key: board.key(),
+            game_state: board.game_state(),
+            length: board.length(),
+            board: format!("{:?}", board),
+            undo: None,
+        }
+    }
+
+    fn check(&self, board: &G) {
+        let context = format!("after reversing {:?}", self.undo.unwrap());
+        assert_eq!(board.uid(), self.uid, "uid changed {}", context);
+        assert_eq!(board.key(), self.key, "key changed {}", context);
+        assert_eq!(
+            board.game_state(),
+            self.game_state,
+            "state changed {}",
+            context
+        );
+        assert_eq!(board.length(), self.length, "length changed {}", context);
+        assert_eq!(
+            format!("{:?}", board),
+            self.board,
+            "board changed {}",
+            context
+        );
+    }
+}
+
+fn check_position<G: Game>(board: &G) {
+    for (i, symmetry) in board.symmetries().iter().enumerate() {
+        assert_eq!(
+            symmetry.game_state(),
+            board.game_state(),
+            "symmetry {} changes the state of\n{:?}",
+            i,
+            board
+        );
+    }
+
+    let [w, h] = G::shape();
+    for player in [board.cur_player(), !board.cur_player()] {
+        assert_eq!(board.vectorize(player).len(), w * h, "vectorize length");
+    }
+
+    let actions: Vec<_> = board.legal_actions().collect();
+    assert_eq!(
+        actions.is_empty(),
+        board.game_state() != GameState::InProgress,
+        "legal actions {:?} in state {:?} for\n{:?}",
+        actions,
+        board.game_state(),
+        board
+    );
+    let mut moves = MoveList::new();
+    board.generate_moves(&mut moves);
+    assert_eq!(
+        moves.len(),
+        actions.len(),
+        "generate_moves and legal_actions"
+    );
+    let mask = actions
+        .iter()
+        .fold(0u128, |mask, &a| mask | 1 << G::action_to_index(a));
+    assert_eq!(mask.count_ones() as usize, actions.len(), "repeated action");
+    assert_eq!(board.legal_mask(), mask, "legal_mask and legal_actions");
+    for &action in moves.iter() {
+        assert!(
+            actions.contains(&action),
+            "{:?} not in legal_actions",
+            action
+        );
+    }
+}
+
+fn check_uid<G: Game>(
+    board: &G,
+    boards: &mut HashMap<G::Uid, String>,
+    uids: &mut HashMap<String, G::Uid>,
+) {
+    let uid = board.uid();
+    let printed = format!("{:?}", board);
+    let other = boards.entry(uid).or_insert_with(|| printed.clone());
+    assert_eq!(*other, printed, "two boards with uid {:?}", uid);
+    let other = uids.entry(printed).or_insert(uid);
+    assert_eq!(*other, uid, "the same board with two uids\n{:?}", board);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::games::connect4::Connect4;
+    use crate::games::hex::{Hex11, Hex7};
+    use crate::games::mnk::{Gomoku, TicTacToe};
+    use crate::games::othello::Othello;
+    use crate::games::popout::PopOut;
+    use crate::games::stack4::Stack4;
+
+    #[test]
+    fn connect4() {
+        check_game::<Connect4>(200, 1);
+    }
+
+    #[test]
+    fn stack4() {
+        check_game::<Stack4>(100, 2);
+    }
+
+    #[test]
+    fn other_games() {
+        check_game::<TicTacToe>(100, 3);
+        check_game::<Gomoku>(10, 7);
+        check_game::<Othello>(10, 4);
+        check_game::<Hex7>(20, 5);
+        check_game::<Hex11>(5, 8);
+        check_game::<PopOut>(10, 6);
+    }
+}
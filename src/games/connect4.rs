@@ -1,8 +1,12 @@
+use crate::games::notation::Notation;
+use crate::games::threats::Threats;
 use crate::games::Game;
-use crate::games::{GameState, Player};
+use crate::games::{
+    center_out, move_key, player_to_move, position_key, position_state, sort_by_priority,
+    GameState, MoveList, Player, PositionError, Rules, WinCondition, MAX_LINE,
+};
 use crate::matchmaker::PlayableGame;
 use serde::{Deserialize, Serialize};
-use smallvec::SmallVec;
 use std::fmt;
 use std::io;
 use std::io::BufRead;
@@ -17,189 +21,468 @@ pub const REWARD_DRAW: f64 = 0.0;
 
 pub type Action = usize; // a value in the range of [0,BOARD_WIDTH)
 
+// Widest board supported by ConnectN.
+pub const MAX_WIDTH: usize = 16;
+
+// The standard game, 7 columns, 6 rows and four in a row wins.
+pub type Connect4 = ConnectN<BOARD_WIDTH, BOARD_HEIGHT, 4>;
+
+// Pieces are dropped into one of W columns that are H tiles high, first player to get K in a
+// row wins unless other rules are given with 'with_rules'. W * (H + 1) must be at most 128 so
+// 9x7 and 8x8 fit but 10x12 doesn't.
 #[derive(Clone, Copy, Serialize, Deserialize)]
-pub struct Connect4 {
-    // tile on board takes up 2 bits, 0 for empty, 1 for red, 2 for yellow.
-    // starts in bottom left corner and goes row by row.
-    pub board: u128,
+pub struct ConnectN<const W: usize, const H: usize, const K: usize> {
+    // One bitboard per player. Bits go column by column starting in the bottom left corner,
+    // see COLUMN_STRIDE.
+    pub red: u128,
+    pub yellow: u128,
+    // Number of pieces in every column, only the first W are used.
+    pub heights: [u8; MAX_WIDTH],
     pub cur_player: Player,
     pub game_state: GameState,
     pub nb_moves: u32,
+    pub rules: Rules,
+    // Zobrist key, see Game::key.
+    pub key: u64,
+    // Number of lines of rules.line_length tiles without any opponent piece, indexed by Player
+    // as usize - 1. Kept up to date by play_action, when both are 0 the game is a draw.
+    pub(crate) live_lines: [u16; 2],
 }
 
-impl Connect4 {
-    pub fn player_won(&self, piece_pos: [usize; 2]) -> bool {
-        let directions: [[i32; 2]; 4] = [[1, 0], [0, 1], [-1, 1], [1, 1]];
-        let player = self.get(piece_pos[0], piece_pos[1]);
-        for direction in directions {
-            let mut sm = 1;
-            for i in 1..4 {
-                let curx = direction[0] * i + piece_pos[0] as i32;
-                let cury = direction[1] * i + piece_pos[1] as i32;
-                if !self.in_board(curx, cury) {
-                    break;
-                } else if player as u8 != self.get(curx as usize, cury as usize) {
-                    break;
+const fn bottom_mask(width: usize, stride: usize) -> u128 {
+    let mut mask = 0;
+    let mut x = 0;
+    while x < width {
+        mask |= 1 << (x * stride);
+        x += 1;
+    }
+    mask
+}
+
+impl<const W: usize, const H: usize, const K: usize> ConnectN<W, H, K> {
+    // Every column takes up H + 1 bits in the bitboards, the extra bit on top of each column
+    // is always empty so that shifting a bitboard never wraps around to another column.
+    pub(crate) const COLUMN_STRIDE: usize = H + 1;
+    pub(crate) const COLUMN_MASK: u128 = (1 << H) - 1;
+    const BOTTOM_MASK: u128 = bottom_mask(W, Self::COLUMN_STRIDE);
+    const BOARD_MASK: u128 = Self::BOTTOM_MASK * Self::COLUMN_MASK;
+
+    // Shifts that move one step vertically, diagonally down, horizontally and diagonally up.
+    const DIRECTIONS: [usize; 4] = [
+        1,
+        Self::COLUMN_STRIDE - 1,
+        Self::COLUMN_STRIDE,
+        Self::COLUMN_STRIDE + 1,
+    ];
+
+    const VALID_DIMENSIONS: () = assert!(
+        W <= MAX_WIDTH && W * (H + 1) <= 128 && K >= 2 && K <= MAX_LINE && H > 0,
+        "board does not fit in the bitboards"
+    );
+
+    pub(crate) fn bit(x: usize, y: usize) -> u128 {
+        1 << Self::tile(x, y)
+    }
+
+    // Index of the bit for (x, y) in the bitboards.
+    pub(crate) fn tile(x: usize, y: usize) -> usize {
+        x * Self::COLUMN_STRIDE + y
+    }
+
+    // Recomputes the Zobrist key after the board was changed other than by playing actions.
+    pub(crate) fn update_key(&mut self) {
+        self.key = position_key(self.red, self.yellow, self.cur_player);
+    }
+
+    pub(crate) fn column_mask(x: usize) -> u128 {
+        Self::COLUMN_MASK << (x * Self::COLUMN_STRIDE)
+    }
+
+    pub fn with_rules(rules: Rules) -> Self {
+        let mut board = Self::new();
+        board.rules = rules;
+        board.update_live_lines();
+        board
+    }
+
+    // Tiles in 'free' where a line of 'n' tiles in direction 'd' starts and only goes through
+    // tiles in 'free'. The empty row on top stops lines from wrapping around.
+    fn line_starts(free: u128, n: usize, d: usize) -> u128 {
+        let mut starts = free;
+        for _ in 1..n {
+            starts = free & (starts >> d);
+        }
+        starts
+    }
+
+    // Number of lines through 'tile' that don't go through any of 'pieces'.
+    fn lines_through(&self, tile: usize, pieces: u128) -> u16 {
+        let n = self.rules.line_length;
+        let free = Self::BOARD_MASK & !pieces;
+        Self::DIRECTIONS
+            .iter()
+            .map(|&d| {
+                let mut starts = 0;
+                for i in (0..n).take_while(|i| i * d <= tile) {
+                    starts |= 1 << (tile - i * d);
                 }
-                sm += 1;
+                (Self::line_starts(free, n, d) & starts).count_ones() as u16
+            })
+            .sum()
+    }
+
+    // Recomputes live_lines after the board or the rules were changed other than by playing
+    // actions.
+    pub(crate) fn update_live_lines(&mut self) {
+        let n = self.rules.line_length;
+        for player in [Player::Red, Player::Yellow] {
+            let free = Self::BOARD_MASK & !self.pieces(!player);
+            self.live_lines[player as usize - 1] = Self::DIRECTIONS
+                .iter()
+                .map(|&d| Self::line_starts(free, n, d).count_ones() as u16)
+                .sum();
+        }
+    }
+
+    // True if no player can get a line anymore.
+    pub fn is_dead(&self) -> bool {
+        self.live_lines == [0, 0]
+    }
+
+    // Builds a position from 'cells' given column by column from the bottom left corner where
+    // 0 is empty, 1 is red and 2 is yellow. The player to move, number of moves and game state
+    // are computed from the pieces, 'cur_player' is only checked against them.
+    pub fn from_cells(cells: &[u8], cur_player: Option<Player>) -> Result<Self, PositionError> {
+        if cells.len() != W * H {
+            return Err(PositionError::WrongSize {
+                expected: W * H,
+                found: cells.len(),
+            });
+        }
+        let mut board = Self::new();
+        for (i, &value) in cells.iter().enumerate() {
+            let (x, y) = (i / H, i % H);
+            match value {
+                0 => {}
+                1 => board.red |= Self::bit(x, y),
+                2 => board.yellow |= Self::bit(x, y),
+                _ => return Err(PositionError::InvalidCell { x, y, value }),
             }
-            for i in 1..4 {
-                let i = -i;
-                let curx = direction[0] * i + piece_pos[0] as i32;
-                let cury = direction[1] * i + piece_pos[1] as i32;
-                if !self.in_board(curx, cury) {
-                    break;
-                } else if player as u8 != self.get(curx as usize, cury as usize) {
-                    break;
-                }
-                sm += 1;
+        }
+        let occupied = board.red | board.yellow;
+        // pieces with an empty tile below them.
+        let floating = occupied & !((occupied << 1) | Self::BOTTOM_MASK);
+        if floating != 0 {
+            let i = floating.trailing_zeros() as usize;
+            return Err(PositionError::FloatingPiece {
+                x: i / Self::COLUMN_STRIDE,
+                y: i % Self::COLUMN_STRIDE,
+            });
+        }
+        for x in 0..W {
+            board.heights[x] = (occupied & Self::column_mask(x)).count_ones() as u8;
+        }
+        let red = board.red.count_ones();
+        let yellow = board.yellow.count_ones();
+        board.cur_player = player_to_move(red, yellow, cur_player)?;
+        board.nb_moves = red + yellow;
+        board.update_live_lines();
+        board.game_state = position_state(
+            [board.has_line(board.red), board.has_line(board.yellow)],
+            board.is_full() || board.is_dead(),
+            board.cur_player,
+            &board.rules,
+        )?;
+        board.update_key();
+        Ok(board)
+    }
+
+    // Returns true if there is a line in 'pieces' that counts under self.rules.
+    pub(crate) fn has_line(&self, pieces: u128) -> bool {
+        let n = self.rules.line_length;
+        Self::DIRECTIONS.iter().any(|&d| {
+            let mut run = pieces;
+            for i in 1..n {
+                run &= pieces >> (i * d);
             }
-            if sm >= 4 {
-                return true;
+            if self.rules.win_condition == WinCondition::Exact {
+                // the tiles just before and after the line must not be in 'pieces'.
+                run &= !(pieces << d) & !(pieces >> ((n - 1) * d) >> d);
             }
+            run != 0
+        })
+    }
+
+    // Returns all tiles, empty or not, that would complete a line of length 'n' for 'pieces'.
+    fn line_tiles(n: usize, pieces: u128) -> u128 {
+        let mut tiles = 0;
+        for d in Self::DIRECTIONS {
+            // before[i] are the tiles whose i closest neighbours in the negative direction
+            // all are in 'pieces', after[i] the same in the positive direction.
+            let mut before = [Self::BOARD_MASK; MAX_LINE];
+            let mut after = [Self::BOARD_MASK; MAX_LINE];
+            for i in 1..n {
+                before[i] = before[i - 1] & (pieces << (i * d));
+                after[i] = after[i - 1] & (pieces >> (i * d));
+            }
+            for i in 0..n {
+                tiles |= before[i] & after[n - 1 - i];
+            }
+        }
+        tiles & Self::BOARD_MASK
+    }
+
+    // Returns the tiles in 'tiles' that would give 'pieces' a line that counts.
+    fn completing_tiles(&self, pieces: u128, tiles: u128) -> u128 {
+        let mut completing = tiles & Self::line_tiles(self.rules.line_length, pieces);
+        if self.rules.win_condition == WinCondition::Exact {
+            // the line might get too long so every tile has to be checked.
+            let mut rest = completing;
+            while rest != 0 {
+                let tile = rest & rest.wrapping_neg();
+                rest &= rest - 1;
+                if !self.has_line(pieces | tile) {
+                    completing &= !tile;
+                }
+            }
+        }
+        completing
+    }
+
+    // Converts a bitboard to the square numbering of vectorize, row by row from the bottom.
+    fn squares(tiles: u128) -> u128 {
+        let mut squares = 0;
+        let mut rest = tiles;
+        while rest != 0 {
+            let tile = rest.trailing_zeros() as usize;
+            rest &= rest - 1;
+            squares |= 1 << (tile % Self::COLUMN_STRIDE * W + tile / Self::COLUMN_STRIDE);
         }
-        false
+        squares
     }
 
+    // Columns ordered from the middle of the board out towards the edges.
+    pub(crate) fn column_order() -> impl Iterator<Item = usize> {
+        (0..W).map(|i| center_out(W, i))
+    }
+
+    // Returns true if the player who owns the piece at 'piece_pos' has won under self.rules.
+    pub fn player_won(&self, piece_pos: [usize; 2]) -> bool {
+        let player = match self.get(piece_pos[0], piece_pos[1]) {
+            1 => Player::Red,
+            2 => Player::Yellow,
+            _ => return false,
+        };
+        [player, !player]
+            .into_iter()
+            .any(|p| self.rules.line_winner(p) == player && self.has_line(self.pieces(p)))
+    }
+
+    // The squares that would give either player a line, on this board's bitboard layout.
+    pub fn threats(&self) -> Threats {
+        let empty = Self::BOARD_MASK & !(self.red | self.yellow);
+        let squares = [Player::Red, Player::Yellow]
+            .map(|player| self.completing_tiles(self.pieces(player), empty));
+        let odd_rows = (0..H)
+            .step_by(2)
+            .fold(0, |rows, y| rows | Self::BOTTOM_MASK << y);
+        Threats::new(squares, self.playable_tiles(), odd_rows, |tiles| {
+            (tiles >> 1) & Self::BOARD_MASK
+        })
+    }
+
+    // Returns true if 'player' would win by placing a piece in column 'action'.
     pub fn is_winning_action(&self, action: Action, player: Player) -> bool {
-        let mut resulting_board = self.clone();
-        resulting_board.play_action(action);
-        resulting_board.game_state == GameState::Won(player)
+        let ap = self.action_pos(action);
+        self.rules.line_winner(player) == player
+            && self.has_line(self.pieces(player) | Self::bit(ap[0], ap[1]))
     }
 
     pub fn in_board(&self, x: i32, y: i32) -> bool {
-        x >= 0 && y >= 0 && x < BOARD_WIDTH as i32 && y < BOARD_HEIGHT as i32
+        x >= 0 && y >= 0 && x < W as i32 && y < H as i32
+    }
+
+    pub fn pieces(&self, player: Player) -> u128 {
+        match player {
+            Player::Red => self.red,
+            Player::Yellow => self.yellow,
+        }
+    }
+
+    pub(crate) fn pieces_mut(&mut self, player: Player) -> &mut u128 {
+        match player {
+            Player::Red => &mut self.red,
+            Player::Yellow => &mut self.yellow,
+        }
+    }
+
+    // Bitboard of all tiles where a piece can be placed.
+    fn playable_tiles(&self) -> u128 {
+        ((self.red | self.yellow) + Self::BOTTOM_MASK) & Self::BOARD_MASK
     }
 
     // Returns where piece will be placed if 'action' is played.
     fn action_pos(&self, action: Action) -> [usize; 2] {
-        for cur_y in 0..BOARD_HEIGHT {
-            if self.get(action, cur_y) == 0 {
-                return [action, cur_y];
-            }
-        }
-        unimplemented!()
+        [action, self.heights[action] as usize]
     }
 
     // Returns where piece placed from last played action 'action'
     fn pos_from_action(&self, action: Action) -> [usize; 2] {
-        for cur_y in 1..BOARD_HEIGHT {
-            if self.get(action, cur_y) == 0 {
-                return [action, cur_y - 1];
-            }
-        }
-        [action, BOARD_HEIGHT - 1]
+        [action, self.heights[action] as usize - 1]
     }
 
+    // From the pieces and not the number of moves, which in PopOut also counts pops.
     pub fn is_full(&self) -> bool {
-        !(0..N_ACTIONS).any(|action| self.is_valid_move(action))
+        self.red | self.yellow == Self::BOARD_MASK
     }
 
     pub fn is_valid_move(&self, action: Action) -> bool {
-        assert!(action < BOARD_WIDTH);
-        self.get(action, BOARD_HEIGHT - 1) == 0
+        assert!(action < W);
+        (self.heights[action] as usize) < H
     }
 
     // mirrors board around the middle of the board.
-    // assumes BOARD_WIDTH = 7. so if BOARD_WIDTH changes then so must this function
-    pub fn symmetry(&self) -> Connect4 {
-        //let col_mask: u128 = (((((((((3<<BOARD_WIDTH*2)+3)<<BOARD_WIDTH*2)+3)<<BOARD_WIDTH*2)+3)<<BOARD_WIDTH*2)+3)<<BOARD_WIDTH*2)+3;
-        let mut col_mask: u128 = 0;
-        for _ in 0..BOARD_HEIGHT {
-            col_mask = (col_mask << BOARD_WIDTH * 2) + 3;
-        }
-        let mut new_board: u128 = 0;
-        for i in 0..3 {
-            new_board += (self.board & (col_mask << (i * 2))) << ((3 - i) * 4);
-        }
-        for i in 0..3 {
-            new_board += (self.board & (col_mask << (4 + i) * 2)) >> (i + 1) * 4;
-        }
-        new_board += self.board & (col_mask << 3 * 2);
-
-        Connect4 {
-            board: new_board,
+    pub fn symmetry(&self) -> Self {
+        let mirror = |pieces: u128| {
+            let mut mirrored = 0;
+            for x in 0..W {
+                let column = (pieces >> (x * Self::COLUMN_STRIDE)) & Self::COLUMN_MASK;
+                mirrored |= column << ((W - 1 - x) * Self::COLUMN_STRIDE);
+            }
+            mirrored
+        };
+        let mut heights = self.heights;
+        heights[..W].reverse();
+        let mut mirrored = ConnectN {
+            red: mirror(self.red),
+            yellow: mirror(self.yellow),
+            heights,
             ..*self
-        }
+        };
+        mirrored.update_key();
+        mirrored
     }
 
     pub fn set(&mut self, x: usize, y: usize, v: u8) {
-        let k = 2 * (x + y * BOARD_WIDTH);
-        let mask = 3 << k;
-        self.board = (self.board & (!mask)) + ((v as u128) << k);
+        let b = Self::bit(x, y);
+        self.red &= !b;
+        self.yellow &= !b;
+        match v {
+            1 => self.red |= b,
+            2 => self.yellow |= b,
+            _ => {}
+        }
+        let column = ((self.red | self.yellow) >> (x * Self::COLUMN_STRIDE)) & Self::COLUMN_MASK;
+        self.heights[x] = (128 - column.leading_zeros()) as u8;
+        self.update_key();
+        self.update_live_lines();
     }
 
     pub fn get(&self, x: usize, y: usize) -> u8 {
-        3 & (self.board >> (2 * (x + y * BOARD_WIDTH))) as u8
+        let b = Self::bit(x, y);
+        if self.red & b != 0 {
+            1
+        } else if self.yellow & b != 0 {
+            2
+        } else {
+            0
+        }
     }
 }
 
-impl Game for Connect4 {
+impl<const W: usize, const H: usize, const K: usize> Game for ConnectN<W, H, K> {
     type Action = usize;
+    type Undo = Self::Action;
+    type Uid = u128;
 
     fn new() -> Self {
-        Connect4 {
-            board: 0,
+        let () = Self::VALID_DIMENSIONS;
+        let mut board = ConnectN {
+            red: 0,
+            yellow: 0,
+            heights: [0; MAX_WIDTH],
             cur_player: Player::Red,
             game_state: GameState::InProgress,
             nb_moves: 0,
-        }
+            rules: Rules::new(WinCondition::Standard, K),
+            key: 0,
+            live_lines: [0; 2],
+        };
+        board.update_live_lines();
+        board
     }
 
     // Plays action for player self.cur_player
-    fn play_action(&mut self, action: Action) {
+    fn play_action(&mut self, action: Action) -> Action {
         assert_eq!(self.game_state, GameState::InProgress);
         if !self.is_valid_move(action) {
-            return;
+            return action;
         }
         let ap = self.action_pos(action);
-        self.set(ap[0], ap[1], self.cur_player as u8);
+        let player = self.cur_player;
+        let tile = Self::tile(ap[0], ap[1]);
+        // the opponent can't complete the lines through the tile anymore.
+        self.live_lines[!player as usize - 1] -= self.lines_through(tile, self.pieces(player));
+        *self.pieces_mut(player) |= 1 << tile;
+        self.key ^= move_key(player, tile);
+        self.heights[action] += 1;
         self.nb_moves += 1;
 
-        if self.player_won(ap) {
-            self.game_state = GameState::Won(self.cur_player);
-        } else if self.is_full() {
+        if self.has_line(self.pieces(player)) {
+            self.game_state = GameState::Won(self.rules.line_winner(player));
+        } else if self.is_full() || self.is_dead() {
             self.game_state = GameState::Draw;
         } else {
             self.game_state = GameState::InProgress;
         }
-        self.cur_player = !self.cur_player;
+        self.cur_player = !player;
+        action
     }
 
     // Reverses last action if the last action is last_action.
     fn reverse_last_action(&mut self, last_action: Action) {
         let ap = self.pos_from_action(last_action);
-        self.set(ap[0], ap[1], 0);
+        let player = !self.cur_player;
+        let tile = Self::tile(ap[0], ap[1]);
+        *self.pieces_mut(player) &= !(1 << tile);
+        self.key ^= move_key(player, tile);
+        self.live_lines[!player as usize - 1] += self.lines_through(tile, self.pieces(player));
+        self.heights[last_action] -= 1;
         self.game_state = GameState::InProgress;
-        self.cur_player = !self.cur_player;
+        self.cur_player = player;
         self.nb_moves -= 1;
     }
 
-    fn legal_actions(&self) -> Box<dyn Iterator<Item = Action>> {
-        let mut winning_moves = SmallVec::<[Action; BOARD_WIDTH]>::new();
-
-        // moves that block the opponent from winning next turn.
-        let mut blocking_moves = SmallVec::<[Action; BOARD_WIDTH]>::new();
-        let moves = [3, 4, 2, 5, 1, 6, 0];
-        //let moves = [0, 1, 2, 3, 4, 5, 6];
-        let mut v = SmallVec::<[Action; BOARD_WIDTH]>::new();
-        for i in moves {
-            if self.is_valid_move(i) {
-                if self.is_winning_action(i, self.cur_player) {
-                    winning_moves.push(i);
-                } else if self.is_winning_action(i, !self.cur_player) {
-                    blocking_moves.push(i);
+    // Columns from the middle out.
+    fn generate_moves(&self, moves: &mut MoveList<Action>) {
+        if self.game_state != GameState::InProgress {
+            return;
+        }
+        let playable = self.playable_tiles();
+        moves.extend(Self::column_order().filter(|&i| playable & Self::column_mask(i) != 0));
+    }
+
+    // Winning moves first, then moves that block the opponent from winning next turn. In misere
+    // the moves that make a line lose and are put last instead.
+    fn order_moves(&self, moves: &mut MoveList<Action>) {
+        let threats = self.threats();
+        let completing = threats.immediate(self.cur_player);
+        let blocking = threats.immediate(!self.cur_player);
+        let misere = self.rules.win_condition == WinCondition::Misere;
+        sort_by_priority(moves, |i| {
+            let column = Self::column_mask(i);
+            if completing & column != 0 {
+                if misere {
+                    3
                 } else {
-                    v.push(i);
+                    0
                 }
+            } else if blocking & column != 0 && !misere {
+                1
+            } else {
+                2
             }
-        }
-        Box::new(
-            winning_moves
-                .into_iter()
-                .chain(blocking_moves.into_iter())
-                .chain(v.into_iter()),
-        )
+        });
     }
 
     fn game_state(&self) -> GameState {
@@ -211,42 +494,82 @@ impl Game for Connect4 {
     }
 
     fn vectorize(&self, player: Player) -> Vec<f64> {
-        let mut v = Vec::with_capacity(BOARD_WIDTH * BOARD_HEIGHT);
-        let mut board = self.board;
-        for _ in 0..BOARD_WIDTH {
-            for _ in 0..BOARD_HEIGHT {
-                let cur = board as u8 & 3;
-                if cur == player as u8 {
+        let own = self.pieces(player);
+        let opponent = self.pieces(!player);
+        let mut v = Vec::with_capacity(W * H);
+        for y in 0..H {
+            for x in 0..W {
+                let b = Self::bit(x, y);
+                if own & b != 0 {
                     v.push(1.0);
-                } else if cur == !player as u8 {
+                } else if opponent & b != 0 {
                     v.push(-1.0);
                 } else {
                     v.push(0.0);
                 }
-                board >>= 2;
             }
         }
         v
     }
     fn shape() -> [usize; 2] {
-        [BOARD_WIDTH, BOARD_HEIGHT]
+        [W, H]
+    }
+    fn action_count() -> usize {
+        W
+    }
+    fn action_to_index(action: Action) -> usize {
+        action
+    }
+    fn index_to_action(index: usize) -> Action {
+        index
+    }
+    fn legal_mask(&self) -> u128 {
+        if self.game_state != GameState::InProgress {
+            return 0;
+        }
+        (0..W)
+            .filter(|&x| (self.heights[x] as usize) < H)
+            .fold(0, |mask, x| mask | 1 << x)
+    }
+    fn legal_squares(&self) -> u128 {
+        if self.game_state != GameState::InProgress {
+            return 0;
+        }
+        Self::squares(self.playable_tiles())
+    }
+    fn threat_squares(&self, player: Player) -> u128 {
+        Self::squares(self.threats().squares(player))
+    }
+    fn live_lines(&self, player: Player) -> Option<u32> {
+        Some(self.live_lines[player as usize - 1] as u32)
     }
     fn uid(&self) -> u128 {
-        self.board
+        // (red | yellow) + bottom sets the bit above the top piece of every column, so the
+        // highest bit of a column gives its height and the bits below it are the red pieces.
+        self.red + (self.red | self.yellow) + Self::BOTTOM_MASK
+    }
+    fn key(&self) -> u64 {
+        self.key
     }
     fn symmetries(&self) -> Vec<Self> {
         vec![self.clone(), self.symmetry()]
     }
+    fn transform_action(&self, action: Action, symmetry: usize) -> Action {
+        match symmetry {
+            0 => action,
+            _ => W - 1 - action,
+        }
+    }
     fn length(&self) -> u32 {
         self.nb_moves
     }
 }
 
-impl fmt::Debug for Connect4 {
+impl<const W: usize, const H: usize, const K: usize> fmt::Debug for ConnectN<W, H, K> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut s = String::new();
-        for y in (0..BOARD_HEIGHT).rev() {
-            for x in 0..BOARD_WIDTH {
+        for y in (0..H).rev() {
+            for x in 0..W {
                 match self.get(x, y) {
                     0 => s.push_str("# "),
                     1 => {
@@ -263,32 +586,58 @@ impl fmt::Debug for Connect4 {
     }
 }
 
-impl PlayableGame for Connect4 {
+impl<const W: usize, const H: usize, const K: usize> PlayableGame for ConnectN<W, H, K> {
     // returns (action, is_reverse)
     fn get_action_from_user(&self) -> (Action, bool) {
+        let threats = self.threats().immediate(!self.cur_player);
+        if threats != 0 && self.rules.win_condition != WinCondition::Misere {
+            let columns: Vec<_> = (0..W)
+                .filter(|&x| threats & Self::column_mask(x) != 0)
+                .map(|x| (x + 1).to_string())
+                .collect();
+            println!(
+                "{:?} threatens column {}",
+                !self.cur_player,
+                columns.join(", ")
+            );
+        }
         let stdin = io::stdin();
         for line in stdin.lock().lines() {
             let line = line.unwrap();
-            if line.as_bytes()[0] == 'z' as u8 {
+            let line = line.trim();
+            if line == "z" {
                 return (0, true);
-            } else if let Ok(a) = line.parse::<usize>() {
-                if a < BOARD_WIDTH {
-                    if !self.is_valid_move(a) {
-                        println!("Column alread full");
-                        continue;
-                    }
-                    return (a, false);
-                } else {
-                    println!("Not in range 0..{}", BOARD_WIDTH);
+            } else if let Some((a, 1)) = Self::read_action(line).filter(|_| line.len() == 1) {
+                if !self.is_valid_move(a) {
+                    println!("Column alread full");
+                    continue;
                 }
+                return (a, false);
             } else {
-                println!("Invalid input: try again");
+                println!("Not a column in 1..={}", W.min(9));
             }
         }
         panic!("Failed to get input from user");
     }
 }
 
+// Columns are written as 1-based digits, so the sequence "4453" starts in the middle column.
+// Only boards up to 9 columns wide can be written.
+impl<const W: usize, const H: usize, const K: usize> Notation for ConnectN<W, H, K> {
+    fn read_action(s: &str) -> Option<(Action, usize)> {
+        let digit = (*s.as_bytes().first()? as char).to_digit(10)? as usize;
+        if digit >= 1 && digit <= W.min(9) {
+            Some((digit - 1, 1))
+        } else {
+            None
+        }
+    }
+
+    fn write_action(action: Action, s: &mut String) {
+        s.push_str(&(action + 1).to_string());
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -336,6 +685,266 @@ mod test {
         board.play_action(0);
         board.reverse_last_action(0);
         println!("{:?}\n{:?}", old_board, board);
-        assert_eq!(old_board.board, board.board);
+        assert_eq!(old_board.uid(), board.uid());
+        assert_eq!(old_board.heights, board.heights);
+    }
+
+    #[test]
+    fn winning_and_blocking_moves_first() {
+        let mut board = Connect4::new();
+        // red threatens to win in column 0 and yellow in column 6.
+        for mv in [0, 6, 0, 6, 0, 6] {
+            board.play_action(mv);
+        }
+        let actions: Vec<_> = board.legal_actions().collect();
+        assert_eq!(actions[..2], [0, 6]);
+        assert!(board.is_winning_action(0, Player::Red));
+        assert!(board.is_winning_action(6, Player::Yellow));
+        assert!(!board.is_winning_action(3, Player::Red));
+    }
+
+    #[test]
+    fn generate_and_order_moves() {
+        let mut board = Connect4::new();
+        for mv in [0, 6, 0, 6, 0, 6] {
+            board.play_action(mv);
+        }
+        let mut moves = MoveList::new();
+        board.generate_moves(&mut moves);
+        assert_eq!(moves[..], [3, 4, 2, 5, 1, 6, 0]);
+        board.order_moves(&mut moves);
+        assert_eq!(moves[..], [0, 6, 3, 4, 2, 5, 1]);
+        assert!(moves.iter().copied().eq(board.legal_actions()));
+        assert!(!moves.spilled());
+    }
+
+    #[test]
+    fn symmetry() {
+        let mut board = Connect4::new();
+        for mv in [0, 1, 1, 5, 2] {
+            board.play_action(mv);
+        }
+        let mirrored = board.symmetry();
+        for x in 0..BOARD_WIDTH {
+            for y in 0..BOARD_HEIGHT {
+                assert_eq!(board.get(x, y), mirrored.get(BOARD_WIDTH - 1 - x, y));
+            }
+        }
+        assert_eq!(mirrored.heights[..BOARD_WIDTH], [0, 1, 0, 0, 1, 2, 1]);
+        assert_eq!(mirrored.symmetry().uid(), board.uid());
+    }
+
+    #[test]
+    fn connect_n() {
+        // connect-3 on a 5x4 board.
+        let mut board = ConnectN::<5, 4, 3>::new();
+        for mv in [0, 4, 1, 4] {
+            board.play_action(mv);
+        }
+        let actions: Vec<_> = board.legal_actions().collect();
+        assert_eq!(actions[..2], [2, 4]);
+        board.play_action(2);
+        assert_eq!(board.game_state, GameState::Won(Player::Red));
+
+        // connect-5 on a 9x7 board, the last columns don't fit in 64 bits.
+        let mut board = ConnectN::<9, 7, 5>::new();
+        for mv in [8, 7, 8, 7, 8, 7, 8, 7] {
+            board.play_action(mv);
+        }
+        assert_eq!(board.game_state, GameState::InProgress);
+        let old_board = board;
+        board.play_action(8);
+        assert_eq!(board.game_state, GameState::Won(Player::Red));
+        board.reverse_last_action(8);
+        assert_eq!(board.uid(), old_board.uid());
+        assert_eq!(board.symmetry().get(0, 3), 1);
+        assert_eq!(board.symmetry().symmetry().uid(), board.uid());
+    }
+
+    #[test]
+    fn misere_and_exact_rules() {
+        use crate::games::{Rules, WinCondition};
+        // red plays the bottom row, yellow the row above it.
+        let moves = [0, 0, 1, 1, 2, 2, 4, 4];
+
+        let mut board = Connect4::with_rules(Rules::new(WinCondition::Misere, 4));
+        for mv in moves {
+            board.play_action(mv);
+        }
+        let actions: Vec<_> = board.legal_actions().collect();
+        assert_eq!(actions.last(), Some(&3));
+        assert!(!board.is_winning_action(3, Player::Red));
+        board.play_action(3);
+        assert_eq!(board.game_state, GameState::Won(Player::Yellow));
+        assert!(board.player_won([0, 1]));
+        assert!(!board.player_won([0, 0]));
+
+        // filling column 3 makes five in a row which doesn't count.
+        let mut board = Connect4::with_rules(Rules::new(WinCondition::Exact, 4));
+        for mv in moves {
+            board.play_action(mv);
+        }
+        assert!(!board.is_winning_action(3, Player::Red));
+        board.play_action(3);
+        board.play_action(3);
+        assert_eq!(board.game_state, GameState::InProgress);
+
+        // connect-3 with the standard board.
+        let mut board = Connect4::with_rules(Rules::new(WinCondition::Standard, 3));
+        for mv in [0, 0, 1, 1] {
+            board.play_action(mv);
+        }
+        assert!(board.is_winning_action(2, Player::Red));
+        board.play_action(2);
+        assert_eq!(board.game_state, GameState::Won(Player::Red));
+    }
+
+    #[test]
+    fn zobrist_key() {
+        let mut board = Connect4::new();
+        let mut other = Connect4::new();
+        for (a, b) in [(0, 2), (1, 1), (2, 0)] {
+            board.play_action(a);
+            other.play_action(b);
+        }
+        assert_eq!(board.key(), other.key());
+        board.play_action(3);
+        assert_ne!(board.key(), other.key());
+        board.reverse_last_action(3);
+        assert_eq!(board.key(), other.key());
+        assert_eq!(board.symmetry().symmetry().key(), board.key());
+    }
+
+    #[test]
+    fn canonical() {
+        let mut board = Connect4::new();
+        board.play_action(0);
+        let mut mirrored = Connect4::new();
+        mirrored.play_action(6);
+        assert_eq!(board.canonical_uid(), mirrored.canonical_uid());
+        let (_, s) = board.canonical();
+        assert_eq!(board.transform_action(2, 1), 4);
+        assert_eq!(board.untransform_action(board.transform_action(2, s), s), 2);
+    }
+
+    #[test]
+    fn action_indices_and_masks() {
+        let mut board = Connect4::new();
+        for _ in 0..BOARD_HEIGHT {
+            board.play_action(1);
+        }
+        board.play_action(4);
+        assert_eq!(board.legal_mask(), 0b1111101);
+        assert_eq!(Connect4::index_to_action(5), 5);
+
+        let symmetry = board.symmetries()[1];
+        let transformed = board.legal_actions().fold(0, |m, a| {
+            m | 1 << Connect4::action_to_index(board.transform_action(a, 1))
+        });
+        assert_eq!(symmetry.legal_mask(), transformed);
+        assert_eq!(symmetry.legal_mask(), 0b1011111);
+    }
+
+    #[test]
+    fn live_lines() {
+        assert_eq!(Connect4::new().live_lines(Player::Red), Some(69));
+        let rng = fastrand::Rng::with_seed(1);
+        for _ in 0..50 {
+            let mut board = Connect4::new();
+            let mut played = Vec::new();
+            while board.game_state == GameState::InProgress {
+                let actions: Vec<_> = board.legal_actions().collect();
+                let action = actions[rng.usize(..actions.len())];
+                board.play_action(action);
+                played.push(action);
+                let mut counted = board;
+                counted.update_live_lines();
+                assert_eq!(board.live_lines, counted.live_lines);
+            }
+            for action in played.into_iter().rev() {
+                board.reverse_last_action(action);
+            }
+            assert_eq!(board.live_lines, Connect4::new().live_lines);
+        }
+
+        // every line is blocked with four empty tiles left.
+        let moves = [
+            0, 5, 4, 1, 1, 2, 6, 2, 4, 6, 0, 0, 1, 3, 0, 6, 2, 5, 5, 4, 6, 1, 5, 2, 4, 5, 1, 4, 4,
+            0, 2, 5, 0, 3, 6, 2, 3,
+        ];
+        let mut board = Connect4::new();
+        for mv in moves {
+            board.play_action(mv);
+        }
+        assert_eq!(board.game_state, GameState::InProgress);
+        board.play_action(3);
+        assert!(board.is_dead() && !board.is_full());
+        assert_eq!(board.game_state, GameState::Draw);
+        assert_eq!(board.live_lines(Player::Yellow), Some(0));
+        board.reverse_last_action(3);
+        assert_eq!(board.game_state, GameState::InProgress);
+        assert!(!board.is_dead());
+    }
+
+    #[test]
+    fn from_cells() {
+        use crate::games::PositionError;
+        let cells = |board: &Connect4| {
+            let mut cells = Vec::new();
+            for x in 0..BOARD_WIDTH {
+                for y in 0..BOARD_HEIGHT {
+                    cells.push(board.get(x, y));
+                }
+            }
+            cells
+        };
+        let mut board = Connect4::new();
+        for mv in [3, 3, 4, 2, 4] {
+            board.play_action(mv);
+        }
+        let built = Connect4::from_cells(&cells(&board), None).unwrap();
+        assert_eq!(built.uid(), board.uid());
+        assert_eq!(built.key(), board.key());
+        assert_eq!(built.heights, board.heights);
+        assert_eq!(built.cur_player, Player::Yellow);
+        assert_eq!(built.nb_moves, 5);
+        assert_eq!(
+            Connect4::from_cells(&cells(&board), Some(Player::Red)).unwrap_err(),
+            PositionError::WrongPlayerToMove(Player::Red)
+        );
+
+        for mv in [0, 5, 0, 6] {
+            board.play_action(mv);
+        }
+        let built = Connect4::from_cells(&cells(&board), None).unwrap();
+        assert_eq!(built.game_state, GameState::Won(Player::Red));
+
+        let mut floating = cells(&Connect4::new());
+        floating[BOARD_HEIGHT + 1] = 1;
+        assert_eq!(
+            Connect4::from_cells(&floating, None).unwrap_err(),
+            PositionError::FloatingPiece { x: 1, y: 1 }
+        );
+        assert_eq!(
+            Connect4::from_cells(&[2; BOARD_WIDTH * BOARD_HEIGHT], None).unwrap_err(),
+            PositionError::PieceCount { red: 0, yellow: 42 }
+        );
+
+        // red and yellow both have four in a row in the bottom two rows.
+        let mut both = cells(&Connect4::new());
+        for x in 0..4 {
+            both[x * BOARD_HEIGHT] = 1;
+            both[x * BOARD_HEIGHT + 1] = 2;
+        }
+        assert_eq!(
+            Connect4::from_cells(&both, None).unwrap_err(),
+            PositionError::BothHaveLines
+        );
+        both[3 * BOARD_HEIGHT + 1] = 0;
+        both[6 * BOARD_HEIGHT] = 2;
+        assert_eq!(
+            Connect4::from_cells(&both, None).unwrap_err(),
+            PositionError::MovedAfterLine(Player::Red)
+        );
     }
 }
@@ -0,0 +1,480 @@
+use crate::games::Game;
+use crate::games::{
+    move_key, parse_cord, position_key, sort_by_priority, GameState, MoveList, Player,
+};
+use crate::matchmaker::PlayableGame;
+use std::fmt;
+use std::io::BufRead;
+
+pub type Hex7 = Hex<7>;
+pub type Hex11 = Hex<11>;
+
+type Action = (usize, usize);
+
+const MAX_SIZE: usize = 11;
+const MAX_TILES: usize = MAX_SIZE * MAX_SIZE;
+
+// Extra nodes in the disjoint sets for the four edges of the board. Red connects the
+// bottom and top edges and yellow connects the left and right edges.
+const BOTTOM: usize = MAX_TILES;
+const TOP: usize = MAX_TILES + 1;
+const LEFT: usize = MAX_TILES + 2;
+const RIGHT: usize = MAX_TILES + 3;
+const NB_NODES: usize = MAX_TILES + 4;
+
+// The six neighbours of a tile on the rhombus.
+const NEIGHBOURS: [(isize, isize); 6] = [(1, 0), (-1, 0), (0, 1), (0, -1), (1, -1), (-1, 1)];
+
+// Everything needed to undo a move. Placing a stone joins at most six neighbouring groups
+// plus an edge, 'roots' are the roots that were attached to another root in the order they
+// were attached and 'ranked' has bit i set if attaching roots[i] increased the rank of its
+// new parent.
+#[derive(Clone, Copy, Debug)]
+pub struct Undo {
+    action: Action,
+    roots: [u8; 7],
+    nb_roots: u8,
+    ranked: u8,
+}
+
+// Hex on a N by N rhombus, the player that connects their two edges wins. Since one of the
+// players always connects their edges once the board is full the game can't end in a draw.
+// N can be from 5 up to 11.
+#[derive(Clone, Copy)]
+pub struct Hex<const N: usize> {
+    // One bitboard per player, row by row starting in the bottom left corner.
+    pub red: u128,
+    pub yellow: u128,
+    // Disjoint sets of connected stones and edges. Uses union by rank without path
+    // compression so that every union can be undone by resetting a single parent.
+    parent: [u8; NB_NODES],
+    rank: [u8; NB_NODES],
+    pub cur_player: Player,
+    pub game_state: GameState,
+    pub nb_moves: u32,
+    // Zobrist key, see Game::key.
+    pub key: u64,
+}
+
+impl<const N: usize> Hex<N> {
+    const VALID_SIZE: () = assert!(N >= 5 && N <= MAX_SIZE, "Hex board must be 5x5 to 11x11");
+
+    fn bit(x: usize, y: usize) -> u128 {
+        1 << (x + y * N)
+    }
+
+    pub fn pieces(&self, player: Player) -> u128 {
+        match player {
+            Player::Red => self.red,
+            Player::Yellow => self.yellow,
+        }
+    }
+
+    fn pieces_mut(&mut self, player: Player) -> &mut u128 {
+        match player {
+            Player::Red => &mut self.red,
+            Player::Yellow => &mut self.yellow,
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        let b = Self::bit(x, y);
+        if self.red & b != 0 {
+            1
+        } else if self.yellow & b != 0 {
+            2
+        } else {
+            0
+        }
+    }
+
+    fn find(&self, mut node: usize) -> usize {
+        while self.parent[node] as usize != node {
+            node = self.parent[node] as usize;
+        }
+        node
+    }
+
+    // Joins the sets of 'a' and 'b' and records it in 'undo' if they were disjoint.
+    fn union(&mut self, a: usize, b: usize, undo: &mut Undo) {
+        let a = self.find(a);
+        let b = self.find(b);
+        if a == b {
+            return;
+        }
+        let (child, root) = if self.rank[a] < self.rank[b] {
+            (a, b)
+        } else {
+            (b, a)
+        };
+        self.parent[child] = root as u8;
+        if self.rank[child] == self.rank[root] {
+            self.rank[root] += 1;
+            undo.ranked |= 1 << undo.nb_roots;
+        }
+        undo.roots[undo.nb_roots as usize] = child as u8;
+        undo.nb_roots += 1;
+    }
+
+    // The edges that the tile (x, y) touches for 'player'.
+    fn edges(player: Player, x: usize, y: usize) -> impl Iterator<Item = usize> {
+        let (low, high, c) = match player {
+            Player::Red => (BOTTOM, TOP, y),
+            Player::Yellow => (LEFT, RIGHT, x),
+        };
+        [(c == 0, low), (c == N - 1, high)]
+            .into_iter()
+            .filter(|&(touches, _)| touches)
+            .map(|(_, edge)| edge)
+    }
+
+    fn neighbours(x: usize, y: usize) -> impl Iterator<Item = (usize, usize)> {
+        NEIGHBOURS.into_iter().filter_map(move |(dx, dy)| {
+            let nx = x as isize + dx;
+            let ny = y as isize + dy;
+            if nx >= 0 && ny >= 0 && (nx as usize) < N && (ny as usize) < N {
+                Some((nx as usize, ny as usize))
+            } else {
+                None
+            }
+        })
+    }
+
+    fn has_connected(&self, player: Player) -> bool {
+        match player {
+            Player::Red => self.find(BOTTOM) == self.find(TOP),
+            Player::Yellow => self.find(LEFT) == self.find(RIGHT),
+        }
+    }
+
+    // Builds a board with the given stones, the player to move is the one with fewer stones.
+    fn from_pieces(red: u128, yellow: u128, nb_moves: u32) -> Self {
+        let mut board = Self::new();
+        let mut rest = red | yellow;
+        while rest != 0 {
+            let i = rest.trailing_zeros() as usize;
+            rest &= rest - 1;
+            board.cur_player = if red >> i & 1 == 1 {
+                Player::Red
+            } else {
+                Player::Yellow
+            };
+            board.play_action((i % N, i / N));
+        }
+        board.cur_player = if red.count_ones() > yellow.count_ones() {
+            Player::Yellow
+        } else {
+            Player::Red
+        };
+        board.nb_moves = nb_moves;
+        board.update_key();
+        board
+    }
+
+    // Recomputes the Zobrist key after the board was changed other than by playing actions.
+    fn update_key(&mut self) {
+        self.key = position_key(self.red, self.yellow, self.cur_player);
+    }
+}
+
+impl<const N: usize> Game for Hex<N> {
+    type Action = Action;
+    type Undo = Undo;
+    // The red and yellow stones, an 11x11 board takes 121 bits each.
+    type Uid = (u128, u128);
+
+    fn new() -> Self {
+        let () = Self::VALID_SIZE;
+        let mut parent = [0; NB_NODES];
+        for (i, p) in parent.iter_mut().enumerate() {
+            *p = i as u8;
+        }
+        Hex {
+            red: 0,
+            yellow: 0,
+            parent,
+            rank: [0; NB_NODES],
+            cur_player: Player::Red,
+            game_state: GameState::InProgress,
+            nb_moves: 0,
+            key: 0,
+        }
+    }
+
+    fn play_action(&mut self, action: Action) -> Undo {
+        let (x, y) = action;
+        let player = self.cur_player;
+        let mut undo = Undo {
+            action,
+            roots: [0; 7],
+            nb_roots: 0,
+            ranked: 0,
+        };
+        let tile = x + y * N;
+        let own = self.pieces(player);
+        for (nx, ny) in Self::neighbours(x, y) {
+            if own & Self::bit(nx, ny) != 0 {
+                self.union(tile, nx + ny * N, &mut undo);
+            }
+        }
+        for edge in Self::edges(player, x, y) {
+            self.union(tile, edge, &mut undo);
+        }
+        *self.pieces_mut(player) |= Self::bit(x, y);
+        self.key ^= move_key(player, tile);
+        if self.has_connected(player) {
+            self.game_state = GameState::Won(player);
+        }
+        self.cur_player = !player;
+        self.nb_moves += 1;
+        undo
+    }
+
+    fn reverse_last_action(&mut self, last_action: Undo) {
+        let (x, y) = last_action.action;
+        for i in (0..last_action.nb_roots as usize).rev() {
+            let child = last_action.roots[i] as usize;
+            if last_action.ranked >> i & 1 == 1 {
+                self.rank[self.parent[child] as usize] -= 1;
+            }
+            self.parent[child] = child as u8;
+        }
+        self.cur_player = !self.cur_player;
+        *self.pieces_mut(self.cur_player) &= !Self::bit(x, y);
+        self.key ^= move_key(self.cur_player, x + y * N);
+        self.game_state = GameState::InProgress;
+        self.nb_moves -= 1;
+    }
+
+    fn game_state(&self) -> GameState {
+        self.game_state
+    }
+
+    fn cur_player(&self) -> Player {
+        self.cur_player
+    }
+
+    fn generate_moves(&self, moves: &mut MoveList<Action>) {
+        if self.game_state != GameState::InProgress {
+            return;
+        }
+        let taken = self.red | self.yellow;
+        for y in 0..N {
+            for x in 0..N {
+                if taken & Self::bit(x, y) == 0 {
+                    moves.push((x, y));
+                }
+            }
+        }
+    }
+
+    // Empty tiles ordered by their distance from the center of the board.
+    fn order_moves(&self, moves: &mut MoveList<Action>) {
+        let center = (N / 2) as isize;
+        // Hex distance on the rhombus.
+        sort_by_priority(moves, |(x, y)| {
+            let dx = x as isize - center;
+            let dy = y as isize - center;
+            ((dx.abs() + dy.abs() + (dx + dy).abs()) / 2) as u8
+        });
+    }
+
+    fn vectorize(&self, player: Player) -> Vec<f64> {
+        let own = self.pieces(player);
+        let opponent = self.pieces(!player);
+        let mut v = Vec::with_capacity(N * N);
+        for i in 0..N * N {
+            if own >> i & 1 == 1 {
+                v.push(1.0);
+            } else if opponent >> i & 1 == 1 {
+                v.push(-1.0);
+            } else {
+                v.push(0.0);
+            }
+        }
+        v
+    }
+
+    fn shape() -> [usize; 2] {
+        [N, N]
+    }
+
+    // The rhombus only has the identity and the half turn as symmetries that keep the colours
+    // of the edges.
+    fn symmetries(&self) -> Vec<Self> {
+        let half_turn = |pieces: u128| {
+            let mut turned = 0;
+            let mut rest = pieces;
+            while rest != 0 {
+                let i = rest.trailing_zeros() as usize;
+                rest &= rest - 1;
+                turned |= 1 << (N * N - 1 - i);
+            }
+            turned
+        };
+        let mut turned =
+            Self::from_pieces(half_turn(self.red), half_turn(self.yellow), self.nb_moves);
+        turned.cur_player = self.cur_player;
+        turned.game_state = self.game_state;
+        turned.update_key();
+        vec![*self, turned]
+    }
+
+    fn transform_action(&self, action: Action, symmetry: usize) -> Action {
+        match symmetry {
+            0 => action,
+            _ => (N - 1 - action.0, N - 1 - action.1),
+        }
+    }
+
+    fn uid(&self) -> (u128, u128) {
+        (self.red, self.yellow)
+    }
+
+    fn key(&self) -> u64 {
+        self.key
+    }
+
+    fn length(&self) -> u32 {
+        self.nb_moves
+    }
+
+    fn action_count() -> usize {
+        N * N
+    }
+
+    fn action_to_index(action: Action) -> usize {
+        action.0 + action.1 * N
+    }
+
+    fn index_to_action(index: usize) -> Action {
+        (index % N, index / N)
+    }
+
+    fn legal_squares(&self) -> u128 {
+        self.legal_mask()
+    }
+}
+
+impl<const N: usize> fmt::Debug for Hex<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = String::new();
+        for y in (0..N).rev() {
+            // shift every row half a tile so that the board is drawn as a rhombus.
+            s.push_str(&" ".repeat(y));
+            for x in 0..N {
+                match self.get(x, y) {
+                    0 => s.push_str("# "),
+                    1 => {
+                        s.push_str("\x1b[30;41m \x1b[0m ");
+                    }
+                    _ => {
+                        s.push_str("\x1b[30;43m \x1b[0m ");
+                    }
+                }
+            }
+            s.push('\n');
+        }
+        write!(f, "{}", &s)
+    }
+}
+
+impl<const N: usize> PlayableGame for Hex<N> {
+    // returns (action, is_reverse)
+    fn get_action_from_user(&self) -> (Action, bool) {
+        let stdin = std::io::stdin();
+
+        for line in stdin.lock().lines() {
+            let line = line.unwrap();
+            let line = line.trim();
+            if line == "z" {
+                return ((0, 0), true);
+            } else if let Some((x, y)) = parse_cord(line) {
+                if x < N && y < N {
+                    if self.get(x, y) != 0 {
+                        println!("Tile already taken");
+                        continue;
+                    }
+                    return ((x, y), false);
+                } else {
+                    println!("Not in range (0..{}, 0..{})", N, N);
+                }
+            } else {
+                println!("Invalid input: try again");
+            }
+        }
+        panic!("Failed to get input from user");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Hex, Hex7};
+    use crate::games::{Game, GameState, Player};
+
+    #[test]
+    fn red_connects_bottom_and_top() {
+        let mut board = Hex::<5>::new();
+        for y in 0..4 {
+            board.play_action((2, y));
+            board.play_action((0, y));
+        }
+        assert_eq!(board.game_state(), GameState::InProgress);
+        board.play_action((2, 4));
+        assert_eq!(board.game_state(), GameState::Won(Player::Red));
+    }
+
+    #[test]
+    fn yellow_connects_left_and_right() {
+        let mut board = Hex7::new();
+        // the diagonal (x, 6 - x) is connected for yellow, red plays next to it.
+        for x in 0..7 {
+            board.play_action((x, (7 - x) % 7));
+            assert_eq!(board.game_state(), GameState::InProgress);
+            board.play_action((x, 6 - x));
+        }
+        assert_eq!(board.game_state(), GameState::Won(Player::Yellow));
+    }
+
+    #[test]
+    fn reverse_restores_the_sets() {
+        let mut board = Hex7::new();
+        let mut undos = Vec::new();
+        // play random games to the end and undo all moves.
+        for _ in 0..20 {
+            while board.game_state() == GameState::InProgress {
+                let actions: Vec<_> = board.legal_actions().collect();
+                undos.push(board.play_action(actions[fastrand::usize(..actions.len())]));
+            }
+            while let Some(undo) = undos.pop() {
+                board.reverse_last_action(undo);
+            }
+            assert_eq!(board.uid(), Hex7::new().uid());
+            assert_eq!(board.parent, Hex7::new().parent);
+            assert_eq!(board.rank, Hex7::new().rank);
+        }
+    }
+
+    #[test]
+    fn full_board_has_a_winner() {
+        let mut board = Hex::<5>::new();
+        while board.game_state() == GameState::InProgress {
+            let action = board.legal_actions().next().unwrap();
+            board.play_action(action);
+        }
+        assert_ne!(board.game_state(), GameState::Draw);
+        assert!(board.nb_moves <= 25);
+    }
+
+    #[test]
+    fn symmetries() {
+        let mut board = Hex7::new();
+        board.play_action((0, 0));
+        board.play_action((1, 0));
+        let symmetries = board.symmetries();
+        assert_eq!(symmetries.len(), 2);
+        assert_eq!(symmetries[1].get(6, 6), 1);
+        assert_eq!(symmetries[1].get(5, 6), 2);
+        assert_eq!(symmetries[1].cur_player(), Player::Red);
+    }
+}
@@ -0,0 +1,509 @@
+use crate::games::Game;
+use crate::games::{
+    center_out, move_key, parse_cord, position_key, sort_by_priority, GameState, MoveList, Player,
+};
+use crate::matchmaker::PlayableGame;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::BufRead;
+
+pub type TicTacToe = MNK<3, 3, 3>;
+pub type Gomoku = MNK<9, 9, 5>;
+
+type Action = (usize, usize);
+
+// The m,n,k-game, players take turns placing a piece anywhere on a M wide and N high
+// board and the first player to get K in a row wins. (M + 1) * N must be at most 128.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct MNK<const M: usize, const N: usize, const K: usize> {
+    // One bitboard per player. Bits go row by row starting in the bottom left corner,
+    // see ROW_STRIDE.
+    pub red: u128,
+    pub yellow: u128,
+    pub cur_player: Player,
+    pub game_state: GameState,
+    pub nb_moves: u32,
+    // Zobrist key, see Game::key.
+    pub key: u64,
+}
+
+const fn row_mask(width: usize, height: usize, stride: usize) -> u128 {
+    let mut mask = 0;
+    let mut y = 0;
+    while y < height {
+        mask |= ((1 << width) - 1) << (y * stride);
+        y += 1;
+    }
+    mask
+}
+
+impl<const M: usize, const N: usize, const K: usize> MNK<M, N, K> {
+    // Every row takes up M + 1 bits in the bitboards, the extra bit at the end of each row
+    // is always empty so that shifting a bitboard never wraps around to another row.
+    const ROW_STRIDE: usize = M + 1;
+    const BOARD_MASK: u128 = row_mask(M, N, Self::ROW_STRIDE);
+
+    // Shifts that move one step horizontally, diagonally up to the left, vertically
+    // and diagonally up to the right.
+    const DIRECTIONS: [usize; 4] = [
+        1,
+        Self::ROW_STRIDE - 1,
+        Self::ROW_STRIDE,
+        Self::ROW_STRIDE + 1,
+    ];
+
+    const VALID_DIMENSIONS: () = assert!(
+        (M + 1) * N <= 128 && K >= 2 && M > 0 && N > 0,
+        "board does not fit in the bitboards"
+    );
+
+    fn bit(x: usize, y: usize) -> u128 {
+        1 << Self::tile(x, y)
+    }
+
+    fn pos(i: usize) -> Action {
+        (i % Self::ROW_STRIDE, i / Self::ROW_STRIDE)
+    }
+
+    // Removes the unused bit at the end of every row.
+    fn compact(pieces: u128) -> u128 {
+        let mut compact = 0;
+        for y in 0..N {
+            compact |= ((pieces >> (y * Self::ROW_STRIDE)) & ((1 << M) - 1)) << (y * M);
+        }
+        compact
+    }
+
+    // Returns true if there are K pieces in a row in 'pieces'.
+    fn has_line(pieces: u128) -> bool {
+        Self::DIRECTIONS.iter().any(|&d| {
+            let mut run = pieces;
+            for i in 1..K {
+                run &= pieces >> (i * d);
+            }
+            run != 0
+        })
+    }
+
+    // Returns all tiles, empty or not, that would complete K in a row for 'pieces'.
+    fn winning_tiles(pieces: u128) -> u128 {
+        let mut tiles = 0;
+        for d in Self::DIRECTIONS {
+            // before[i] are the tiles whose i closest neighbours in the negative direction
+            // all are in 'pieces', after[i] the same in the positive direction.
+            let mut before = [Self::BOARD_MASK; K];
+            let mut after = [Self::BOARD_MASK; K];
+            for i in 1..K {
+                before[i] = before[i - 1] & (pieces << (i * d));
+                after[i] = after[i - 1] & (pieces >> (i * d));
+            }
+            for i in 0..K {
+                tiles |= before[i] & after[K - 1 - i];
+            }
+        }
+        tiles & Self::BOARD_MASK
+    }
+
+    // Returns true if 'player' would get K in a row by placing a piece at 'action'.
+    pub fn is_winning_action(&self, action: Action, player: Player) -> bool {
+        Self::has_line(self.pieces(player) | Self::bit(action.0, action.1))
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.red | self.yellow == Self::BOARD_MASK
+    }
+
+    pub fn in_board(x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && x < M as i32 && y < N as i32
+    }
+
+    pub fn pieces(&self, player: Player) -> u128 {
+        match player {
+            Player::Red => self.red,
+            Player::Yellow => self.yellow,
+        }
+    }
+
+    fn pieces_mut(&mut self, player: Player) -> &mut u128 {
+        match player {
+            Player::Red => &mut self.red,
+            Player::Yellow => &mut self.yellow,
+        }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, v: u8) {
+        let b = Self::bit(x, y);
+        self.red &= !b;
+        self.yellow &= !b;
+        match v {
+            1 => self.red |= b,
+            2 => self.yellow |= b,
+            _ => {}
+        }
+        self.update_key();
+    }
+
+    // Recomputes the Zobrist key after the board was changed other than by playing actions.
+    fn update_key(&mut self) {
+        self.key = position_key(self.red, self.yellow, self.cur_player);
+    }
+
+    fn tile(x: usize, y: usize) -> usize {
+        x + y * Self::ROW_STRIDE
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        let b = Self::bit(x, y);
+        if self.red & b != 0 {
+            1
+        } else if self.yellow & b != 0 {
+            2
+        } else {
+            0
+        }
+    }
+
+    // Applies 'f' to the coordinates of every piece on the board.
+    fn transform<F: Fn(usize, usize) -> (usize, usize)>(&self, f: F) -> Self {
+        let move_pieces = |pieces: u128| {
+            let mut moved = 0;
+            let mut rest = pieces;
+            while rest != 0 {
+                let (x, y) = Self::pos(rest.trailing_zeros() as usize);
+                rest &= rest - 1;
+                let (nx, ny) = f(x, y);
+                moved |= Self::bit(nx, ny);
+            }
+            moved
+        };
+        let mut transformed = MNK {
+            red: move_pieces(self.red),
+            yellow: move_pieces(self.yellow),
+            ..*self
+        };
+        transformed.update_key();
+        transformed
+    }
+
+    // Returns board rotated by 90 degrees, only possible if the board is square.
+    fn rotation(&self) -> Self {
+        assert_eq!(M, N);
+        self.transform(|x, y| (M - 1 - y, x))
+    }
+
+    // Returns board rotated by 180 degrees.
+    fn half_turn(&self) -> Self {
+        self.transform(|x, y| (M - 1 - x, N - 1 - y))
+    }
+
+    // mirrors board around the middle of the board.
+    pub fn mirror(&self) -> Self {
+        self.transform(|x, y| (M - 1 - x, y))
+    }
+}
+
+impl<const M: usize, const N: usize, const K: usize> Game for MNK<M, N, K> {
+    type Action = (usize, usize); // x,y coordinates of the placed piece.
+    type Undo = Self::Action;
+    // The red and yellow pieces, which take up to 128 bits each.
+    type Uid = (u128, u128);
+
+    fn new() -> Self {
+        let () = Self::VALID_DIMENSIONS;
+        MNK {
+            red: 0,
+            yellow: 0,
+            cur_player: Player::Red,
+            game_state: GameState::InProgress,
+            nb_moves: 0,
+            key: 0,
+        }
+    }
+
+    // Assumes that 'action' is a legal action.
+    fn play_action(&mut self, action: Self::Action) -> Self::Action {
+        assert_eq!(self.game_state, GameState::InProgress);
+        let player = self.cur_player;
+        *self.pieces_mut(player) |= Self::bit(action.0, action.1);
+        self.key ^= move_key(player, Self::tile(action.0, action.1));
+        self.nb_moves += 1;
+
+        if Self::has_line(self.pieces(player)) {
+            self.game_state = GameState::Won(player);
+        } else if self.is_full() {
+            self.game_state = GameState::Draw;
+        } else {
+            self.game_state = GameState::InProgress;
+        }
+        self.cur_player = !player;
+        action
+    }
+
+    fn reverse_last_action(&mut self, last_action: Self::Action) {
+        let player = !self.cur_player;
+        *self.pieces_mut(player) &= !Self::bit(last_action.0, last_action.1);
+        self.key ^= move_key(player, Self::tile(last_action.0, last_action.1));
+        self.game_state = GameState::InProgress;
+        self.cur_player = player;
+        self.nb_moves -= 1;
+    }
+
+    fn game_state(&self) -> GameState {
+        self.game_state
+    }
+
+    fn cur_player(&self) -> Player {
+        self.cur_player
+    }
+
+    // Tiles close to the middle of the board come first.
+    fn generate_moves(&self, moves: &mut MoveList<Action>) {
+        if self.game_state != GameState::InProgress {
+            return;
+        }
+        let empty = Self::BOARD_MASK & !(self.red | self.yellow);
+        for i in 0..N {
+            let y = center_out(N, i);
+            for j in 0..M {
+                let x = center_out(M, j);
+                if empty & Self::bit(x, y) != 0 {
+                    moves.push((x, y));
+                }
+            }
+        }
+    }
+
+    // Winning moves first, then moves that block the opponent from winning next turn.
+    fn order_moves(&self, moves: &mut MoveList<Action>) {
+        let winning = Self::winning_tiles(self.pieces(self.cur_player));
+        let blocking = Self::winning_tiles(self.pieces(!self.cur_player));
+        sort_by_priority(moves, |(x, y)| {
+            let b = Self::bit(x, y);
+            if winning & b != 0 {
+                0
+            } else if blocking & b != 0 {
+                1
+            } else {
+                2
+            }
+        });
+    }
+
+    fn vectorize(&self, player: Player) -> Vec<f64> {
+        let own = self.pieces(player);
+        let opponent = self.pieces(!player);
+        let mut v = Vec::with_capacity(M * N);
+        for y in 0..N {
+            for x in 0..M {
+                let b = Self::bit(x, y);
+                if own & b != 0 {
+                    v.push(1.0);
+                } else if opponent & b != 0 {
+                    v.push(-1.0);
+                } else {
+                    v.push(0.0);
+                }
+            }
+        }
+        v
+    }
+
+    fn shape() -> [usize; 2] {
+        [M, N]
+    }
+
+    // Square boards have all 8 symmetries of the square, rectangular boards only have 4.
+    fn symmetries(&self) -> Vec<Self> {
+        let mut symmetries = Vec::with_capacity(8);
+        if M == N {
+            let mut rotated = *self;
+            for _ in 0..4 {
+                symmetries.push(rotated);
+                symmetries.push(rotated.mirror());
+                rotated = rotated.rotation();
+            }
+        } else {
+            for board in [*self, self.half_turn()] {
+                symmetries.push(board);
+                symmetries.push(board.mirror());
+            }
+        }
+        symmetries
+    }
+
+    // Symmetry 2n is n quarter turns, or n half turns if the board isn't square, and 2n+1 is
+    // the same turn followed by a mirror.
+    fn transform_action(&self, action: Action, symmetry: usize) -> Action {
+        let (mut x, mut y) = action;
+        if M == N {
+            for _ in 0..symmetry / 2 {
+                (x, y) = (M - 1 - y, x);
+            }
+        } else if symmetry / 2 == 1 {
+            (x, y) = (M - 1 - x, N - 1 - y);
+        }
+        if symmetry % 2 == 1 {
+            x = M - 1 - x;
+        }
+        (x, y)
+    }
+
+    fn inverse_symmetry(&self, symmetry: usize) -> usize {
+        if M == N && symmetry % 2 == 0 {
+            (8 - symmetry) % 8
+        } else {
+            symmetry
+        }
+    }
+
+    fn uid(&self) -> (u128, u128) {
+        (Self::compact(self.red), Self::compact(self.yellow))
+    }
+
+    fn key(&self) -> u64 {
+        self.key
+    }
+
+    fn length(&self) -> u32 {
+        self.nb_moves
+    }
+
+    // Squares row by row from the bottom left corner.
+    fn action_count() -> usize {
+        M * N
+    }
+
+    fn action_to_index(action: Action) -> usize {
+        action.0 + action.1 * M
+    }
+
+    fn index_to_action(index: usize) -> Action {
+        (index % M, index / M)
+    }
+
+    fn legal_squares(&self) -> u128 {
+        self.legal_mask()
+    }
+
+    fn threat_squares(&self, player: Player) -> u128 {
+        let empty = Self::BOARD_MASK & !(self.red | self.yellow);
+        Self::compact(empty & Self::winning_tiles(self.pieces(player)))
+    }
+}
+
+impl<const M: usize, const N: usize, const K: usize> fmt::Debug for MNK<M, N, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = String::new();
+        for y in (0..N).rev() {
+            for x in 0..M {
+                match self.get(x, y) {
+                    0 => s.push_str("# "),
+                    1 => {
+                        s.push_str("\x1b[30;41m \x1b[0m ");
+                    }
+                    _ => {
+                        s.push_str("\x1b[30;43m \x1b[0m ");
+                    }
+                }
+            }
+            s.push('\n');
+        }
+        write!(f, "{}", &s)
+    }
+}
+
+impl<const M: usize, const N: usize, const K: usize> PlayableGame for MNK<M, N, K> {
+    // returns (action, is_reverse)
+    fn get_action_from_user(&self) -> (Action, bool) {
+        let stdin = std::io::stdin();
+
+        for line in stdin.lock().lines() {
+            let line = line.unwrap();
+            let line = line.trim();
+            if line == "z" {
+                return ((0, 0), true);
+            } else if let Some((x, y)) = parse_cord(line) {
+                if x < M && y < N {
+                    if self.get(x, y) != 0 {
+                        println!("Tile already taken");
+                        continue;
+                    }
+                    return ((x, y), false);
+                } else {
+                    println!("Not in range (0..{}, 0..{})", M, N);
+                }
+            } else {
+                println!("Invalid input: try again");
+            }
+        }
+        panic!("Failed to get input from user");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Gomoku, TicTacToe};
+    use crate::evaluators::SimpleEval;
+    use crate::games::{Game, GameState, Player};
+    use crate::search::abnegamax;
+
+    #[test]
+    fn tic_tac_toe() {
+        let mut board = TicTacToe::new();
+        for action in [(0, 0), (1, 1), (2, 0)] {
+            board.play_action(action);
+        }
+        assert_eq!(board.legal_actions().next(), Some((1, 0)));
+        board.play_action((1, 0));
+        assert_eq!(board.legal_actions().next(), Some((1, 2)));
+        assert_eq!(board.legal_actions().count(), 5);
+
+        let mut board = TicTacToe::new();
+        for action in [(0, 0), (1, 1), (0, 1), (2, 2)] {
+            board.play_action(action);
+            assert_eq!(board.game_state(), GameState::InProgress);
+        }
+        board.play_action((0, 2));
+        assert_eq!(board.game_state(), GameState::Won(Player::Red));
+    }
+
+    #[test]
+    fn symmetries() {
+        let mut board = TicTacToe::new();
+        board.play_action((0, 0));
+        board.play_action((1, 0));
+        let mut uids: Vec<_> = board.symmetries().iter().map(|b| b.uid()).collect();
+        uids.sort();
+        uids.dedup();
+        assert_eq!(uids.len(), 8);
+    }
+
+    #[test]
+    fn tic_tac_toe_is_a_draw() {
+        let board = TicTacToe::new();
+        let (v, _) = abnegamax(&board, 9, 0, &SimpleEval::new(), Player::Red, None);
+        assert_eq!(v, 0.0);
+    }
+
+    #[test]
+    fn gomoku() {
+        let mut board = Gomoku::new();
+        // red pieces at the end of the bottom row and the start of the next row.
+        for action in [
+            (6, 0),
+            (0, 8),
+            (7, 0),
+            (1, 8),
+            (8, 0),
+            (2, 8),
+            (0, 1),
+            (3, 8),
+        ] {
+            board.play_action(action);
+        }
+        board.play_action((1, 1));
+        assert_eq!(board.game_state(), GameState::InProgress);
+        board.play_action((4, 8));
+        assert_eq!(board.game_state(), GameState::Won(Player::Yellow));
+    }
+}
@@ -1,30 +1,133 @@
+pub mod conformance;
 pub mod connect4;
+pub mod hex;
+pub mod mnk;
+pub mod notation;
+pub mod othello;
+pub mod popout;
 pub mod stack4;
+pub mod threats;
 
 use num_derive::FromPrimitive;
 use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
 use std::fmt;
+use std::hash::Hash;
 use std::ops;
 
 // A two player with three possible outcomes, win for either player or a draw.
 pub trait Game: Clone + Copy + fmt::Debug {
-    type Action: Copy + fmt::Debug;
+    type Action: Copy + fmt::Debug + PartialEq + 'static;
+
+    // What reverse_last_action needs to undo an action. For games where the action itself is
+    // enough this is just the action, but for example Othello also has to remember which
+    // discs were flipped.
+    type Undo: Copy + fmt::Debug;
+
+    // Identifies a position exactly, see uid. Games whose pieces fit in 128 bits use a u128,
+    // larger boards need more.
+    type Uid: Copy + Eq + Ord + Hash + fmt::Debug + 'static;
 
     fn new() -> Self;
-    fn play_action(&mut self, action: Self::Action);
-    fn reverse_last_action(&mut self, last_action: Self::Action);
+
+    // Plays 'action' for the current player and returns what is needed to reverse it.
+    fn play_action(&mut self, action: Self::Action) -> Self::Undo;
+    fn reverse_last_action(&mut self, last_action: Self::Undo);
 
     fn game_state(&self) -> GameState;
     fn cur_player(&self) -> Player;
 
-    fn legal_actions(&self) -> Box<dyn Iterator<Item = Self::Action>>;
+    // Adds the legal actions to 'moves' in the cheapest order to generate them. Adds nothing
+    // once the game is over and at least one action while it is in progress, a player that
+    // cannot move gets a pass action.
+    fn generate_moves(&self, moves: &mut MoveList<Self::Action>);
+
+    // Reorders 'moves', the legal actions of self, so that the most promising are first.
+    // Only worth calling when the order matters, like in alpha-beta search.
+    fn order_moves(&self, _moves: &mut MoveList<Self::Action>) {}
+
+    // The legal actions in the order given by order_moves.
+    fn legal_actions(&self) -> Box<dyn Iterator<Item = Self::Action>> {
+        let mut moves = MoveList::new();
+        self.generate_moves(&mut moves);
+        self.order_moves(&mut moves);
+        Box::new(moves.into_iter())
+    }
+
+    // Number of distinct actions. action_to_index gives every action its own index below this
+    // and index_to_action maps it back, so the index can be used for policy outputs. At most
+    // 128 so that legal_mask fits in a u128.
+    fn action_count() -> usize;
+    fn action_to_index(action: Self::Action) -> usize;
+    fn index_to_action(index: usize) -> Self::Action;
+
+    // Has bit action_to_index(a) set for every legal action a.
+    fn legal_mask(&self) -> u128 {
+        self.legal_actions()
+            .fold(0, |mask, a| mask | 1 << Self::action_to_index(a))
+    }
+
+    // Squares that a legal action puts a piece on, bit i is set for square i of vectorize.
+    // Games where actions don't put a piece on a single square keep the default of none.
+    fn legal_squares(&self) -> u128 {
+        0
+    }
+
+    // Empty squares, numbered like in vectorize, where a piece for 'player' would give them a
+    // line. Games without lines keep the default of none.
+    fn threat_squares(&self, _player: Player) -> u128 {
+        0
+    }
+
+    // Number of lines that 'player' could still complete, the ones without any opponent piece.
+    // A game where neither player has one left is a draw. Games that don't keep count, or where
+    // lines can be opened up again, give None.
+    fn live_lines(&self, _player: Player) -> Option<u32> {
+        None
+    }
 
     fn vectorize(&self, player: Player) -> Vec<f64>;
 
     // Returns all states that are equal under symmetry including self.
     fn symmetries(&self) -> Vec<Self>;
 
-    fn uid(&self) -> u128;
+    // Maps 'action' in self to the same action in self.symmetries()[symmetry].
+    fn transform_action(&self, action: Self::Action, symmetry: usize) -> Self::Action;
+
+    // The index of the symmetry that undoes 'symmetry'. Mirrors and half turns are their
+    // own inverse so only games with quarter turns need to change this.
+    fn inverse_symmetry(&self, symmetry: usize) -> usize {
+        symmetry
+    }
+
+    // Maps an action in self.symmetries()[symmetry] back to the same action in self.
+    fn untransform_action(&self, action: Self::Action, symmetry: usize) -> Self::Action {
+        self.transform_action(action, self.inverse_symmetry(symmetry))
+    }
+
+    // The position among symmetries() with the smallest uid and its index. Every symmetric
+    // position has the same canonical position.
+    fn canonical(&self) -> (Self, usize) {
+        self.symmetries()
+            .into_iter()
+            .enumerate()
+            .min_by_key(|(_, board)| board.uid())
+            .map(|(i, board)| (board, i))
+            .unwrap()
+    }
+
+    // uid of the canonical position.
+    fn canonical_uid(&self) -> Self::Uid {
+        self.canonical().0.uid()
+    }
+
+    // Different positions always have different uids, so unlike key it can be used to tell
+    // positions apart.
+    fn uid(&self) -> Self::Uid;
+
+    // Zobrist key of the pieces and the player to move, kept up to date by play_action and
+    // reverse_last_action. Unlike uid different positions can share a key.
+    fn key(&self) -> u64;
 
     // How many moves has been played.
     fn length(&self) -> u32;
@@ -32,6 +135,51 @@ pub trait Game: Clone + Copy + fmt::Debug {
     fn shape() -> [usize; 2];
 }
 
+// Largest number of legal actions in a position of any game, see Game::action_count.
+pub const MAX_ACTIONS: usize = 128;
+
+// Buffer for the legal actions of a position. It holds MAX_ACTIONS actions without
+// allocating so search can reuse one per node.
+pub type MoveList<A> = SmallVec<[A; MAX_ACTIONS]>;
+
+// Stable sort of 'moves' by 'priority', lowest first. Move lists are short so insertion sort
+// is fast enough and unlike slice::sort it never allocates.
+pub fn sort_by_priority<A: Copy>(moves: &mut [A], priority: impl Fn(A) -> u8) {
+    let mut priorities = [0; MAX_ACTIONS];
+    for (p, &action) in priorities.iter_mut().zip(moves.iter()) {
+        *p = priority(action);
+    }
+    for i in 1..moves.len() {
+        let (action, p) = (moves[i], priorities[i]);
+        let mut j = i;
+        while j > 0 && priorities[j - 1] > p {
+            moves[j] = moves[j - 1];
+            priorities[j] = priorities[j - 1];
+            j -= 1;
+        }
+        moves[j] = action;
+        priorities[j] = p;
+    }
+}
+
+// Returns the i:th element of 0..n when ordered from the middle out towards the ends.
+pub(crate) fn center_out(n: usize, i: usize) -> usize {
+    let center = (n - 1) / 2;
+    if i % 2 == 1 {
+        center + i / 2 + 1
+    } else {
+        center - i / 2
+    }
+}
+
+// Parses coordinates given as "x,y".
+pub(crate) fn parse_cord(s: &str) -> Option<(usize, usize)> {
+    let mut numbers = s.split(',');
+    let x = numbers.next()?.parse::<usize>().ok()?;
+    let y = numbers.next()?.parse::<usize>().ok()?;
+    Some((x, y))
+}
+
 // in the boards these are represented by two bit numbers where Empty=0, Full(Red)=1, Full(Yellow)=2
 #[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
 pub enum TileStates {
@@ -61,3 +209,206 @@ impl ops::Not for Player {
         }
     }
 }
+
+// Longest line that a rule set can ask for.
+pub const MAX_LINE: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum WinCondition {
+    // The first player to get a line of at least line_length wins.
+    Standard,
+    // The first player to get a line of at least line_length loses.
+    Misere,
+    // The first player to get a line of exactly line_length wins, longer lines don't count.
+    Exact,
+}
+
+// Rules for games where the goal is to get a number of pieces in a row.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Rules {
+    pub win_condition: WinCondition,
+    pub line_length: usize,
+}
+
+impl Rules {
+    pub fn new(win_condition: WinCondition, line_length: usize) -> Self {
+        assert!(
+            (2..=MAX_LINE).contains(&line_length),
+            "line length must be in 2..={}",
+            MAX_LINE
+        );
+        Rules {
+            win_condition,
+            line_length,
+        }
+    }
+
+    // The player that wins when 'player' makes a line.
+    pub fn line_winner(&self, player: Player) -> Player {
+        match self.win_condition {
+            WinCondition::Misere => !player,
+            WinCondition::Standard | WinCondition::Exact => player,
+        }
+    }
+}
+
+// Largest number of bits in a bitboard that can have a Zobrist key.
+const MAX_TILES: usize = 128;
+
+const fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+const fn zobrist_table() -> [[u64; MAX_TILES]; 2] {
+    let mut table = [[0; MAX_TILES]; 2];
+    let mut i = 0;
+    while i < 2 * MAX_TILES {
+        table[i / MAX_TILES][i % MAX_TILES] = splitmix64(i as u64);
+        i += 1;
+    }
+    table
+}
+
+static ZOBRIST: [[u64; MAX_TILES]; 2] = zobrist_table();
+
+// Part of the key that is set when yellow is to move.
+const YELLOW_TO_MOVE: u64 = splitmix64(2 * MAX_TILES as u64);
+
+// Zobrist key of a piece for 'player' on bit 'tile' of a bitboard.
+pub fn tile_key(player: Player, tile: usize) -> u64 {
+    ZOBRIST[player as usize - 1][tile]
+}
+
+// Zobrist key of all pieces in the bitboard 'pieces' for 'player'.
+pub fn pieces_key(player: Player, pieces: u128) -> u64 {
+    let mut key = 0;
+    let mut rest = pieces;
+    while rest != 0 {
+        key ^= tile_key(player, rest.trailing_zeros() as usize);
+        rest &= rest - 1;
+    }
+    key
+}
+
+// Zobrist key of 'player' being the one to move.
+pub fn side_key(player: Player) -> u64 {
+    match player {
+        Player::Red => 0,
+        Player::Yellow => YELLOW_TO_MOVE,
+    }
+}
+
+// Zobrist key of a whole position, for when it can't be updated incrementally.
+pub fn position_key(red: u128, yellow: u128, cur_player: Player) -> u64 {
+    pieces_key(Player::Red, red) ^ pieces_key(Player::Yellow, yellow) ^ side_key(cur_player)
+}
+
+// Zobrist key of the turn passing to the other player.
+pub fn turn_key() -> u64 {
+    YELLOW_TO_MOVE
+}
+
+// Zobrist key of the change when 'player' places a piece on 'tile' and the turn passes.
+pub fn move_key(player: Player, tile: usize) -> u64 {
+    tile_key(player, tile) ^ YELLOW_TO_MOVE
+}
+
+// Why a grid of cells can't be turned into a position.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PositionError {
+    // The grid doesn't have one cell per tile.
+    WrongSize { expected: usize, found: usize },
+    // A cell that isn't 0 (empty), 1 (red) or 2 (yellow).
+    InvalidCell { x: usize, y: usize, value: u8 },
+    // A piece that couldn't have been placed, like a Connect4 disc with an empty tile below it.
+    FloatingPiece { x: usize, y: usize },
+    // Red moves first so red has as many pieces as yellow or one more.
+    PieceCount { red: u32, yellow: u32 },
+    // The given player can't be the one to move with these piece counts.
+    WrongPlayerToMove(Player),
+    // The game ends as soon as one player has a line so both can't have one.
+    BothHaveLines,
+    // A player has a line but wasn't the last one to move.
+    MovedAfterLine(Player),
+}
+
+impl fmt::Display for PositionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PositionError::WrongSize { expected, found } => {
+                write!(f, "expected {} cells but got {}", expected, found)
+            }
+            PositionError::InvalidCell { x, y, value } => {
+                write!(f, "invalid value {} in cell ({}, {})", value, x, y)
+            }
+            PositionError::FloatingPiece { x, y } => {
+                write!(f, "the piece at ({}, {}) is not supported", x, y)
+            }
+            PositionError::PieceCount { red, yellow } => {
+                write!(
+                    f,
+                    "impossible piece counts, red {} and yellow {}",
+                    red, yellow
+                )
+            }
+            PositionError::WrongPlayerToMove(player) => {
+                write!(f, "{:?} can't be the player to move", player)
+            }
+            PositionError::BothHaveLines => write!(f, "both players have a line"),
+            PositionError::MovedAfterLine(player) => {
+                write!(f, "{:?} has a line but the game went on", player)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PositionError {}
+
+// Returns the player to move given how many pieces each player has. Red always starts.
+pub(crate) fn player_to_move(
+    red: u32,
+    yellow: u32,
+    given: Option<Player>,
+) -> Result<Player, PositionError> {
+    let player = if red == yellow {
+        Player::Red
+    } else if red == yellow + 1 {
+        Player::Yellow
+    } else {
+        return Err(PositionError::PieceCount { red, yellow });
+    };
+    match given {
+        Some(p) if p != player => Err(PositionError::WrongPlayerToMove(p)),
+        _ => Ok(player),
+    }
+}
+
+// Returns the state of a position where 'lines' tells if red and yellow have a line and
+// 'drawn' if the game can't go on without one, because the board is full or no lines are left.
+pub(crate) fn position_state(
+    lines: [bool; 2],
+    drawn: bool,
+    cur_player: Player,
+    rules: &Rules,
+) -> Result<GameState, PositionError> {
+    match lines {
+        [true, true] => Err(PositionError::BothHaveLines),
+        [false, false] if drawn => Ok(GameState::Draw),
+        [false, false] => Ok(GameState::InProgress),
+        _ => {
+            let player = if lines[0] {
+                Player::Red
+            } else {
+                Player::Yellow
+            };
+            if player == cur_player {
+                Err(PositionError::MovedAfterLine(player))
+            } else {
+                Ok(GameState::Won(rules.line_winner(player)))
+            }
+        }
+    }
+}
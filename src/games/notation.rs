@@ -0,0 +1,173 @@
+use crate::games::{Game, GameState};
+use std::fmt;
+
+// Why a move sequence couldn't be read.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NotationError {
+    // Text that isn't a move, 'index' is the byte where it starts.
+    InvalidMove { index: usize },
+    // A move that isn't legal in the position, 'ply' is its number in the sequence from 0.
+    IllegalMove { ply: usize },
+    // A move after the game has ended.
+    GameOver { ply: usize },
+}
+
+impl fmt::Display for NotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotationError::InvalidMove { index } => write!(f, "invalid move at byte {}", index),
+            NotationError::IllegalMove { ply } => write!(f, "move {} is illegal", ply + 1),
+            NotationError::GameOver { ply } => {
+                write!(f, "move {} is played after the game ended", ply + 1)
+            }
+        }
+    }
+}
+
+impl std::error::Error for NotationError {}
+
+// Games whose moves can be written as text. Moves are written one after another without
+// separators, so every move must be readable from the start of a string.
+pub trait Notation: Game {
+    // Reads the move at the start of 's' and returns it together with how many bytes it used.
+    fn read_action(s: &str) -> Option<(Self::Action, usize)>;
+
+    fn write_action(action: Self::Action, s: &mut String);
+}
+
+// A game from the starting position and the moves played in it.
+#[derive(Clone)]
+pub struct GameRecord<G: Game> {
+    pub board: G,
+    pub actions: Vec<G::Action>,
+}
+
+impl<G: Notation> GameRecord<G> {
+    pub fn new() -> Self {
+        GameRecord {
+            board: G::new(),
+            actions: Vec::new(),
+        }
+    }
+
+    // Reads a move sequence like "4453" for Connect4 or "d1e1" for Stack4.
+    pub fn parse(s: &str) -> Result<Self, NotationError> {
+        let mut record = Self::new();
+        let mut index = 0;
+        while index < s.len() {
+            let (action, len) = G::read_action(&s[index..])
+                .filter(|&(_, len)| len > 0)
+                .ok_or(NotationError::InvalidMove { index })?;
+            record.play(action)?;
+            index += len;
+        }
+        Ok(record)
+    }
+
+    // Plays 'action' if it is legal.
+    pub fn play(&mut self, action: G::Action) -> Result<(), NotationError> {
+        let ply = self.actions.len();
+        if self.board.game_state() != GameState::InProgress {
+            return Err(NotationError::GameOver { ply });
+        }
+        if !self.board.legal_actions().any(|a| a == action) {
+            return Err(NotationError::IllegalMove { ply });
+        }
+        self.board.play_action(action);
+        self.actions.push(action);
+        Ok(())
+    }
+}
+
+impl<G: Notation> fmt::Display for GameRecord<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = String::new();
+        for &action in &self.actions {
+            G::write_action(action, &mut s);
+        }
+        write!(f, "{}", s)
+    }
+}
+
+// Prints the board, and with {:#?} also the move sequence.
+impl<G: Notation> fmt::Debug for GameRecord<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.board)?;
+        if f.alternate() {
+            writeln!(f, "{}", self)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::games::connect4::Connect4;
+    use crate::games::stack4::Stack4;
+    use crate::games::Player;
+
+    #[test]
+    fn connect4_sequences() {
+        let record = GameRecord::<Connect4>::parse("4453").unwrap();
+        assert_eq!(record.actions, vec![3, 3, 4, 2]);
+        assert_eq!(record.to_string(), "4453");
+        assert_eq!(record.board.length(), 4);
+
+        assert_eq!(
+            GameRecord::<Connect4>::parse("4444444").unwrap_err(),
+            NotationError::IllegalMove { ply: 6 }
+        );
+        assert_eq!(
+            GameRecord::<Connect4>::parse("44x3").unwrap_err(),
+            NotationError::InvalidMove { index: 2 }
+        );
+        assert_eq!(
+            GameRecord::<Connect4>::parse("8").unwrap_err(),
+            NotationError::InvalidMove { index: 0 }
+        );
+
+        let record = GameRecord::<Connect4>::parse("1212121").unwrap();
+        assert_eq!(record.board.game_state(), GameState::Won(Player::Red));
+        assert_eq!(
+            GameRecord::<Connect4>::parse("12121212").unwrap_err(),
+            NotationError::GameOver { ply: 7 }
+        );
+    }
+
+    #[test]
+    fn stack4_sequences() {
+        let record = GameRecord::<Stack4>::parse("a1h8a2b1").unwrap();
+        assert_eq!(record.actions, vec![(0, 0), (7, 7), (0, 1), (1, 0)]);
+        assert_eq!(record.to_string(), "a1h8a2b1");
+
+        assert_eq!(
+            GameRecord::<Stack4>::parse("d4").unwrap_err(),
+            NotationError::IllegalMove { ply: 0 }
+        );
+        assert_eq!(
+            GameRecord::<Stack4>::parse("a1i1").unwrap_err(),
+            NotationError::InvalidMove { index: 2 }
+        );
+        assert_eq!(
+            GameRecord::<Stack4>::parse("a1a9").unwrap_err(),
+            NotationError::InvalidMove { index: 2 }
+        );
+        assert_eq!(
+            GameRecord::<Stack4>::parse("a0").unwrap_err(),
+            NotationError::InvalidMove { index: 0 }
+        );
+        assert_eq!(
+            GameRecord::<Stack4>::parse("é1").unwrap_err(),
+            NotationError::InvalidMove { index: 0 }
+        );
+        assert_eq!(
+            GameRecord::<Stack4>::parse("a1é").unwrap_err(),
+            NotationError::InvalidMove { index: 2 }
+        );
+
+        let record = GameRecord::<Stack4>::parse("a1h1a2h2a3h3a4").unwrap();
+        assert_eq!(record.board.game_state(), GameState::Won(Player::Red));
+        assert!(format!("{:#?}", record).ends_with("a1h1a2h2a3h3a4\n"));
+    }
+}
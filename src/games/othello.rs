@@ -0,0 +1,512 @@
+use crate::games::Game;
+use crate::games::{
+    parse_cord, pieces_key, position_key, sort_by_priority, turn_key, GameState, MoveList, Player,
+};
+use crate::matchmaker::PlayableGame;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::BufRead;
+
+const BOARD_SIZE: usize = 8;
+
+const FIRST_COLUMN: u64 = 0x0101010101010101;
+const LAST_COLUMN: u64 = FIRST_COLUMN << (BOARD_SIZE - 1);
+
+// One of the four middle tiles, it is never empty.
+const MIDDLE_TILE: u64 = 1 << (3 + 3 * BOARD_SIZE);
+
+// Shifts for the eight directions, positive shifts go left.
+const DIRECTIONS: [i32; 8] = [1, -1, 8, -8, 9, -9, 7, -7];
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Action {
+    // x,y coordinates of the placed disc.
+    Place(usize, usize),
+    // Only legal when the current player has nowhere to place a disc.
+    Pass,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Undo {
+    action: Action,
+    flipped: u64,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Othello {
+    // One bitboard per player, row by row starting in the bottom left corner.
+    // Red is black and moves first.
+    pub red: u64,
+    pub yellow: u64,
+    pub cur_player: Player,
+    pub game_state: GameState,
+    pub nb_moves: u32,
+    // Zobrist key, see Game::key.
+    pub key: u64,
+}
+
+fn bit(x: usize, y: usize) -> u64 {
+    1 << (x + y * BOARD_SIZE)
+}
+
+// Moves every tile one step in the direction given by 'shift', tiles that leave the board are removed.
+fn step(tiles: u64, shift: i32) -> u64 {
+    match shift {
+        1 | 9 | -7 => shift_by(tiles, shift) & !FIRST_COLUMN,
+        -1 | -9 | 7 => shift_by(tiles, shift) & !LAST_COLUMN,
+        _ => shift_by(tiles, shift),
+    }
+}
+
+fn shift_by(tiles: u64, shift: i32) -> u64 {
+    if shift > 0 {
+        tiles << shift
+    } else {
+        tiles >> -shift
+    }
+}
+
+// All empty tiles where 'own' can place a disc.
+fn moves(own: u64, opponent: u64) -> u64 {
+    let empty = !(own | opponent);
+    let mut moves = 0;
+    for d in DIRECTIONS {
+        // opponent discs in a line starting next to one of 'own' discs.
+        let mut line = step(own, d) & opponent;
+        for _ in 0..5 {
+            line |= step(line, d) & opponent;
+        }
+        moves |= step(line, d) & empty;
+    }
+    moves
+}
+
+// Opponent discs that are flipped when 'own' places a disc at 'tile'.
+fn flips(own: u64, opponent: u64, tile: u64) -> u64 {
+    let mut flipped = 0;
+    for d in DIRECTIONS {
+        let mut line = 0;
+        let mut cur = step(tile, d);
+        while cur & opponent != 0 {
+            line |= cur;
+            cur = step(cur, d);
+        }
+        if cur & own != 0 {
+            flipped |= line;
+        }
+    }
+    flipped
+}
+
+impl Othello {
+    pub fn pieces(&self, player: Player) -> u64 {
+        match player {
+            Player::Red => self.red,
+            Player::Yellow => self.yellow,
+        }
+    }
+
+    fn pieces_mut(&mut self, player: Player) -> &mut u64 {
+        match player {
+            Player::Red => &mut self.red,
+            Player::Yellow => &mut self.yellow,
+        }
+    }
+
+    // Bitboard of all tiles where the current player can place a disc.
+    pub fn legal_tiles(&self) -> u64 {
+        moves(self.pieces(self.cur_player), self.pieces(!self.cur_player))
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, v: u8) {
+        let b = bit(x, y);
+        self.red &= !b;
+        self.yellow &= !b;
+        match v {
+            1 => self.red |= b,
+            2 => self.yellow |= b,
+            _ => {}
+        }
+        self.update_key();
+    }
+
+    // Recomputes the Zobrist key after the board was changed other than by playing actions.
+    fn update_key(&mut self) {
+        self.key = position_key(self.red as u128, self.yellow as u128, self.cur_player);
+    }
+
+    // The change of the Zobrist key when 'player' plays 'action' and flips 'flipped'.
+    fn action_key(player: Player, action: Action, flipped: u64) -> u64 {
+        let placed = match action {
+            Action::Place(x, y) => bit(x, y),
+            Action::Pass => 0,
+        };
+        pieces_key(player, (placed | flipped) as u128)
+            ^ pieces_key(!player, flipped as u128)
+            ^ turn_key()
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        let b = bit(x, y);
+        if self.red & b != 0 {
+            1
+        } else if self.yellow & b != 0 {
+            2
+        } else {
+            0
+        }
+    }
+
+    // The game is over when neither player can place a disc, the player with most discs wins.
+    fn update_game_state(&mut self) {
+        if moves(self.red, self.yellow) != 0 || moves(self.yellow, self.red) != 0 {
+            self.game_state = GameState::InProgress;
+            return;
+        }
+        let red = self.red.count_ones();
+        let yellow = self.yellow.count_ones();
+        self.game_state = if red > yellow {
+            GameState::Won(Player::Red)
+        } else if yellow > red {
+            GameState::Won(Player::Yellow)
+        } else {
+            GameState::Draw
+        };
+    }
+
+    // Applies 'f' to the coordinates of every disc on the board.
+    fn transform<F: Fn(usize, usize) -> (usize, usize)>(&self, f: F) -> Self {
+        let move_pieces = |pieces: u64| {
+            let mut moved = 0;
+            let mut rest = pieces;
+            while rest != 0 {
+                let i = rest.trailing_zeros() as usize;
+                rest &= rest - 1;
+                let (nx, ny) = f(i % BOARD_SIZE, i / BOARD_SIZE);
+                moved |= bit(nx, ny);
+            }
+            moved
+        };
+        let mut transformed = Othello {
+            red: move_pieces(self.red),
+            yellow: move_pieces(self.yellow),
+            ..*self
+        };
+        transformed.update_key();
+        transformed
+    }
+}
+
+impl Game for Othello {
+    type Action = Action;
+    type Undo = Undo;
+    type Uid = u128;
+
+    fn new() -> Self {
+        let mut board = Othello {
+            red: 0,
+            yellow: 0,
+            cur_player: Player::Red,
+            game_state: GameState::InProgress,
+            nb_moves: 0,
+            key: 0,
+        };
+        board.set(3, 3, 2);
+        board.set(4, 4, 2);
+        board.set(3, 4, 1);
+        board.set(4, 3, 1);
+        board
+    }
+
+    // Assumes that 'action' is a legal action.
+    fn play_action(&mut self, action: Action) -> Undo {
+        assert_eq!(self.game_state, GameState::InProgress);
+        let player = self.cur_player;
+        let mut flipped = 0;
+        if let Action::Place(x, y) = action {
+            let b = bit(x, y);
+            flipped = flips(self.pieces(player), self.pieces(!player), b);
+            *self.pieces_mut(player) |= b | flipped;
+            *self.pieces_mut(!player) &= !flipped;
+            self.update_game_state();
+        }
+        self.key ^= Self::action_key(player, action, flipped);
+        self.nb_moves += 1;
+        self.cur_player = !player;
+        Undo { action, flipped }
+    }
+
+    fn reverse_last_action(&mut self, last_action: Undo) {
+        let player = !self.cur_player;
+        if let Action::Place(x, y) = last_action.action {
+            *self.pieces_mut(player) &= !(bit(x, y) | last_action.flipped);
+            *self.pieces_mut(!player) |= last_action.flipped;
+        }
+        self.key ^= Self::action_key(player, last_action.action, last_action.flipped);
+        self.game_state = GameState::InProgress;
+        self.cur_player = player;
+        self.nb_moves -= 1;
+    }
+
+    fn game_state(&self) -> GameState {
+        self.game_state
+    }
+
+    fn cur_player(&self) -> Player {
+        self.cur_player
+    }
+
+    fn generate_moves(&self, moves: &mut MoveList<Action>) {
+        if self.game_state != GameState::InProgress {
+            return;
+        }
+        let legal = self.legal_tiles();
+        if legal == 0 {
+            moves.push(Action::Pass);
+        }
+        let mut rest = legal;
+        while rest != 0 {
+            let i = rest.trailing_zeros() as usize;
+            rest &= rest - 1;
+            moves.push(Action::Place(i % BOARD_SIZE, i / BOARD_SIZE));
+        }
+    }
+
+    // Corners first since they can never be flipped, then the rest of the tiles.
+    fn order_moves(&self, moves: &mut MoveList<Action>) {
+        sort_by_priority(moves, |action| match action {
+            Action::Place(0 | 7, 0 | 7) => 0,
+            _ => 1,
+        });
+    }
+
+    fn vectorize(&self, player: Player) -> Vec<f64> {
+        let own = self.pieces(player);
+        let opponent = self.pieces(!player);
+        let mut v = Vec::with_capacity(BOARD_SIZE * BOARD_SIZE);
+        for i in 0..BOARD_SIZE * BOARD_SIZE {
+            if own >> i & 1 == 1 {
+                v.push(1.0);
+            } else if opponent >> i & 1 == 1 {
+                v.push(-1.0);
+            } else {
+                v.push(0.0);
+            }
+        }
+        v
+    }
+
+    fn shape() -> [usize; 2] {
+        [BOARD_SIZE, BOARD_SIZE]
+    }
+
+    fn symmetries(&self) -> Vec<Self> {
+        let m = BOARD_SIZE - 1;
+        let mut symmetries = Vec::with_capacity(8);
+        let mut rotated = *self;
+        for _ in 0..4 {
+            symmetries.push(rotated);
+            symmetries.push(rotated.transform(|x, y| (m - x, y)));
+            rotated = rotated.transform(|x, y| (m - y, x));
+        }
+        symmetries
+    }
+
+    // Symmetry 2n is n quarter turns and 2n+1 is the same turn followed by a mirror.
+    fn transform_action(&self, action: Action, symmetry: usize) -> Action {
+        let m = BOARD_SIZE - 1;
+        match action {
+            Action::Pass => Action::Pass,
+            Action::Place(mut x, mut y) => {
+                for _ in 0..symmetry / 2 {
+                    (x, y) = (m - y, x);
+                }
+                if symmetry % 2 == 1 {
+                    x = m - x;
+                }
+                Action::Place(x, y)
+            }
+        }
+    }
+
+    fn inverse_symmetry(&self, symmetry: usize) -> usize {
+        if symmetry % 2 == 0 {
+            (8 - symmetry) % 8
+        } else {
+            symmetry
+        }
+    }
+
+    // Since a player can pass the side to move isn't given by the discs. The middle tiles are
+    // never empty so whether yellow owns MIDDLE_TILE is already known from red, that bit is
+    // used for the side to move instead.
+    fn uid(&self) -> u128 {
+        let yellow = (self.yellow & !MIDDLE_TILE)
+            | if self.cur_player == Player::Yellow {
+                MIDDLE_TILE
+            } else {
+                0
+            };
+        (yellow as u128) << 64 | self.red as u128
+    }
+
+    fn key(&self) -> u64 {
+        self.key
+    }
+
+    fn length(&self) -> u32 {
+        self.nb_moves
+    }
+
+    // The 64 squares row by row and then the pass.
+    fn action_count() -> usize {
+        65
+    }
+
+    fn action_to_index(action: Action) -> usize {
+        match action {
+            Action::Place(x, y) => x + 8 * y,
+            Action::Pass => 64,
+        }
+    }
+
+    fn index_to_action(index: usize) -> Action {
+        match index {
+            64 => Action::Pass,
+            i => Action::Place(i % 8, i / 8),
+        }
+    }
+
+    fn legal_squares(&self) -> u128 {
+        self.legal_mask() & !(1 << 64)
+    }
+}
+
+impl fmt::Debug for Othello {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = String::new();
+        let legal = self.legal_tiles();
+        for y in (0..BOARD_SIZE).rev() {
+            for x in 0..BOARD_SIZE {
+                match self.get(x, y) {
+                    0 => {
+                        if legal & bit(x, y) != 0 {
+                            s.push_str("O ")
+                        } else {
+                            s.push_str("# ")
+                        }
+                    }
+                    1 => {
+                        s.push_str("\x1b[30;41m \x1b[0m ");
+                    }
+                    _ => {
+                        s.push_str("\x1b[30;43m \x1b[0m ");
+                    }
+                }
+            }
+            s.push('\n');
+        }
+        write!(f, "{}", &s)
+    }
+}
+
+impl PlayableGame for Othello {
+    // returns (action, is_reverse)
+    fn get_action_from_user(&self) -> (Action, bool) {
+        let stdin = std::io::stdin();
+        let legal = self.legal_tiles();
+        if legal == 0 {
+            println!("No legal moves, passing");
+            return (Action::Pass, false);
+        }
+
+        for line in stdin.lock().lines() {
+            let line = line.unwrap();
+            let line = line.trim();
+            if line == "z" {
+                return (Action::Pass, true);
+            } else if let Some((x, y)) = parse_cord(line) {
+                if x < BOARD_SIZE && y < BOARD_SIZE {
+                    if legal & bit(x, y) == 0 {
+                        println!("Illegal action");
+                        continue;
+                    }
+                    return (Action::Place(x, y), false);
+                } else {
+                    println!("Not in range (0..{}, 0..{})", BOARD_SIZE, BOARD_SIZE);
+                }
+            } else {
+                println!("Invalid input: try again");
+            }
+        }
+        panic!("Failed to get input from user");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, Othello};
+    use crate::games::{Game, GameState, Player};
+
+    #[test]
+    fn flips_and_reverse() {
+        let mut board = Othello::new();
+        let start = board;
+        assert_eq!(board.legal_actions().count(), 4);
+        let undo = board.play_action(Action::Place(3, 2));
+        assert_eq!(board.red.count_ones(), 4);
+        assert_eq!(board.yellow.count_ones(), 1);
+        assert_eq!(board.get(3, 3), 1);
+        board.reverse_last_action(undo);
+        assert_eq!(board.uid(), start.uid());
+        assert_eq!(board.cur_player(), Player::Red);
+    }
+
+    #[test]
+    fn pass() {
+        // red has nowhere to place a disc but yellow can still place at (0, 0).
+        let mut board = Othello::new();
+        board.red = 0;
+        board.yellow = 0;
+        board.set(1, 0, 1);
+        for x in 2..8 {
+            board.set(x, 0, 2);
+        }
+        board.set(7, 7, 1);
+        assert_eq!(board.legal_actions().collect::<Vec<_>>(), [Action::Pass]);
+        let before = board;
+        let pass = board.play_action(Action::Pass);
+        assert_eq!(board.game_state(), GameState::InProgress);
+        assert_ne!(board.uid(), before.uid());
+        let actions: Vec<_> = board.legal_actions().collect();
+        assert_eq!(actions, [Action::Place(0, 0)]);
+        let place = board.play_action(Action::Place(0, 0));
+        assert_eq!(board.game_state(), GameState::Won(Player::Yellow));
+        board.reverse_last_action(place);
+        assert_eq!(board.cur_player(), Player::Yellow);
+        board.reverse_last_action(pass);
+        assert_eq!(board.uid(), before.uid());
+        assert_eq!(board.key(), before.key());
+        assert_eq!(board.cur_player(), Player::Red);
+        assert_eq!(board.game_state(), before.game_state());
+        assert_eq!(board.length(), before.length());
+    }
+
+    #[test]
+    fn transform_actions() {
+        let board = Othello::new();
+        let symmetries = board.symmetries();
+        for (s, symmetry) in symmetries.iter().enumerate() {
+            for action in board.legal_actions() {
+                let mut played = board;
+                played.play_action(action);
+                let mut symmetry = *symmetry;
+                let transformed = board.transform_action(action, s);
+                symmetry.play_action(transformed);
+                assert_eq!(symmetry.uid(), played.symmetries()[s].uid());
+                assert_eq!(board.untransform_action(transformed, s), action);
+            }
+        }
+        assert_eq!(board.transform_action(Action::Pass, 3), Action::Pass);
+    }
+}
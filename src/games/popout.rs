@@ -0,0 +1,416 @@
+use crate::games::connect4::{Connect4, BOARD_HEIGHT, BOARD_WIDTH};
+use crate::games::Game;
+use crate::games::{move_key, pieces_key, tile_key, turn_key, GameState, MoveList, Player};
+use crate::matchmaker::PlayableGame;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::io::BufRead;
+
+// How many of the latest positions are remembered for the repetition rule.
+const HISTORY: usize = 32;
+
+// Games that go on for this many moves are declared a draw.
+pub const MAX_MOVES: u32 = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Action {
+    // Drops a disc in the column like in Connect4.
+    Drop(usize),
+    // Removes the current player's disc from the bottom of the column, the discs above fall down.
+    Pop(usize),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Undo {
+    action: Action,
+    // The history entry that was overwritten by the action.
+    previous: u128,
+}
+
+// Connect4 where a player may also pop one of their own discs from the bottom of a column.
+// A full board doesn't end the game, instead it is a draw when the same position occurs for
+// the third time within the last HISTORY moves or after MAX_MOVES moves. If a pop gives both
+// players four in a row the player who popped wins.
+#[derive(Clone, Copy)]
+pub struct PopOut {
+    // The discs, current player, game state and number of moves are kept in a Connect4
+    // board. Its game_state is set by PopOut and not by Connect4::play_action, and its live
+    // lines are not kept up to date since a pop can open a line up again.
+    pub board: Connect4,
+    // uid of the position after move i is at index i % HISTORY.
+    history: [u128; HISTORY],
+}
+
+impl PopOut {
+    // True if the bottom disc of column 'x' belongs to 'player'.
+    pub fn can_pop(&self, x: usize, player: Player) -> bool {
+        self.board.pieces(player) & Connect4::bit(x, 0) != 0
+    }
+
+    // Shifts column 'x' of 'pieces' one step down, the bottom disc is removed.
+    fn shift_down(pieces: u128, x: usize) -> u128 {
+        let column = Connect4::column_mask(x);
+        (pieces & !column) | ((pieces & column) >> 1 & column)
+    }
+
+    // Shifts column 'x' of 'pieces' one step up, the bottom tile is left empty.
+    fn shift_up(pieces: u128, x: usize) -> u128 {
+        let column = Connect4::column_mask(x);
+        (pieces & !column) | ((pieces & column) << 1 & column)
+    }
+
+    // Applies 'shift' to column 'x' of both players and updates the key.
+    fn shift_column(&mut self, x: usize, shift: fn(u128, usize) -> u128) {
+        for player in [Player::Red, Player::Yellow] {
+            let pieces = self.board.pieces(player);
+            let shifted = shift(pieces, x);
+            self.board.key ^= pieces_key(player, pieces ^ shifted);
+            *self.board.pieces_mut(player) = shifted;
+        }
+    }
+
+    // Number of times the current position occured before, among the last HISTORY positions.
+    fn repetitions(&self) -> usize {
+        let n = self.board.nb_moves as usize;
+        let uid = self.history[n % HISTORY];
+        (n.saturating_sub(HISTORY - 1)..n)
+            .filter(|i| self.history[i % HISTORY] == uid)
+            .count()
+    }
+
+    fn update_game_state(&mut self, player: Player) {
+        let won = self.board.has_line(self.board.pieces(player));
+        let opponent_won = self.board.has_line(self.board.pieces(!player));
+        self.board.game_state = if won {
+            GameState::Won(player)
+        } else if opponent_won {
+            GameState::Won(!player)
+        } else if self.repetitions() >= 2 || self.board.nb_moves >= MAX_MOVES {
+            GameState::Draw
+        } else if self.board.is_full() && !(0..BOARD_WIDTH).any(|x| self.can_pop(x, !player)) {
+            // the next player can neither drop nor pop.
+            GameState::Draw
+        } else {
+            GameState::InProgress
+        };
+    }
+}
+
+impl Game for PopOut {
+    type Action = Action;
+    type Undo = Undo;
+    type Uid = u128;
+
+    fn new() -> Self {
+        let board = Connect4::new();
+        let mut history = [0; HISTORY];
+        history[0] = board.uid();
+        PopOut { board, history }
+    }
+
+    // Assumes that 'action' is a legal action.
+    fn play_action(&mut self, action: Action) -> Undo {
+        assert_eq!(self.board.game_state, GameState::InProgress);
+        let player = self.board.cur_player;
+        match action {
+            Action::Drop(x) => {
+                let y = self.board.heights[x] as usize;
+                *self.board.pieces_mut(player) |= Connect4::bit(x, y);
+                self.board.key ^= move_key(player, Connect4::tile(x, y));
+                self.board.heights[x] += 1;
+            }
+            Action::Pop(x) => {
+                self.shift_column(x, Self::shift_down);
+                self.board.key ^= turn_key();
+                self.board.heights[x] -= 1;
+            }
+        }
+        self.board.cur_player = !player;
+        self.board.nb_moves += 1;
+        let i = self.board.nb_moves as usize % HISTORY;
+        let previous = self.history[i];
+        self.history[i] = self.board.uid();
+        self.update_game_state(player);
+        Undo { action, previous }
+    }
+
+    fn reverse_last_action(&mut self, last_action: Undo) {
+        let player = !self.board.cur_player;
+        match last_action.action {
+            Action::Drop(x) => {
+                self.board.heights[x] -= 1;
+                let y = self.board.heights[x] as usize;
+                *self.board.pieces_mut(player) &= !Connect4::bit(x, y);
+                self.board.key ^= move_key(player, Connect4::tile(x, y));
+            }
+            Action::Pop(x) => {
+                self.shift_column(x, Self::shift_up);
+                *self.board.pieces_mut(player) |= Connect4::bit(x, 0);
+                self.board.key ^= tile_key(player, Connect4::tile(x, 0)) ^ turn_key();
+                self.board.heights[x] += 1;
+            }
+        }
+        self.history[self.board.nb_moves as usize % HISTORY] = last_action.previous;
+        self.board.nb_moves -= 1;
+        self.board.cur_player = player;
+        self.board.game_state = GameState::InProgress;
+    }
+
+    fn game_state(&self) -> GameState {
+        self.board.game_state
+    }
+
+    fn cur_player(&self) -> Player {
+        self.board.cur_player
+    }
+
+    // Drops followed by pops, both from the middle out.
+    fn generate_moves(&self, moves: &mut MoveList<Action>) {
+        if self.board.game_state != GameState::InProgress {
+            return;
+        }
+        for x in Connect4::column_order() {
+            if self.board.is_valid_move(x) {
+                moves.push(Action::Drop(x));
+            }
+        }
+        for x in Connect4::column_order() {
+            if self.can_pop(x, self.board.cur_player) {
+                moves.push(Action::Pop(x));
+            }
+        }
+    }
+
+    // Drops in the order Connect4 gives them, the pops stay after them.
+    fn order_moves(&self, moves: &mut MoveList<Action>) {
+        let mut drops = MoveList::new();
+        for &action in moves.iter() {
+            if let Action::Drop(x) = action {
+                drops.push(x);
+            }
+        }
+        self.board.order_moves(&mut drops);
+        for (action, x) in moves.iter_mut().zip(drops) {
+            *action = Action::Drop(x);
+        }
+    }
+
+    fn vectorize(&self, player: Player) -> Vec<f64> {
+        self.board.vectorize(player)
+    }
+
+    fn shape() -> [usize; 2] {
+        [BOARD_WIDTH, BOARD_HEIGHT]
+    }
+
+    // The history is not mirrored so repetitions are only detected for self.
+    fn symmetries(&self) -> Vec<Self> {
+        vec![
+            *self,
+            PopOut {
+                board: self.board.symmetry(),
+                ..*self
+            },
+        ]
+    }
+
+    fn transform_action(&self, action: Action, symmetry: usize) -> Action {
+        match (action, symmetry) {
+            (_, 0) => action,
+            (Action::Drop(x), _) => Action::Drop(BOARD_WIDTH - 1 - x),
+            (Action::Pop(x), _) => Action::Pop(BOARD_WIDTH - 1 - x),
+        }
+    }
+
+    // Every move adds or removes one disc so the number of discs still gives the player to move.
+    fn uid(&self) -> u128 {
+        self.board.uid()
+    }
+
+    fn key(&self) -> u64 {
+        self.board.key
+    }
+
+    fn length(&self) -> u32 {
+        self.board.nb_moves
+    }
+
+    // Drops in the first BOARD_WIDTH indices and pops in the next BOARD_WIDTH.
+    fn action_count() -> usize {
+        2 * BOARD_WIDTH
+    }
+
+    fn action_to_index(action: Action) -> usize {
+        match action {
+            Action::Drop(x) => x,
+            Action::Pop(x) => BOARD_WIDTH + x,
+        }
+    }
+
+    fn index_to_action(index: usize) -> Action {
+        if index < BOARD_WIDTH {
+            Action::Drop(index)
+        } else {
+            Action::Pop(index - BOARD_WIDTH)
+        }
+    }
+
+    // Only drops put a piece on a square.
+    fn legal_squares(&self) -> u128 {
+        if self.board.game_state != GameState::InProgress {
+            return 0;
+        }
+        self.board.legal_squares()
+    }
+
+    fn threat_squares(&self, player: Player) -> u128 {
+        self.board.threat_squares(player)
+    }
+}
+
+impl fmt::Debug for PopOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.board)
+    }
+}
+
+impl PlayableGame for PopOut {
+    // returns (action, is_reverse)
+    // 'x' drops a disc in column x and 'px' pops the bottom disc of column x.
+    fn get_action_from_user(&self) -> (Action, bool) {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            let line = line.unwrap();
+            let line = line.trim();
+            if line == "z" {
+                return (Action::Drop(0), true);
+            }
+            let (pop, column) = match line.strip_prefix('p') {
+                Some(column) => (true, column),
+                None => (false, line),
+            };
+            if let Ok(a) = column.parse::<usize>() {
+                if a >= BOARD_WIDTH {
+                    println!("Not in range 0..{}", BOARD_WIDTH);
+                } else if pop {
+                    if !self.can_pop(a, self.board.cur_player) {
+                        println!("Can only pop your own disc");
+                        continue;
+                    }
+                    return (Action::Pop(a), false);
+                } else {
+                    if !self.board.is_valid_move(a) {
+                        println!("Column alread full");
+                        continue;
+                    }
+                    return (Action::Drop(a), false);
+                }
+            } else {
+                println!("Invalid input: try again");
+            }
+        }
+        panic!("Failed to get input from user");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, PopOut};
+    use crate::games::{Game, GameState, Player};
+
+    #[test]
+    fn pop_and_reverse() {
+        let mut board = PopOut::new();
+        for a in [0, 0, 0, 1] {
+            board.play_action(Action::Drop(a));
+        }
+        assert!(board.legal_actions().any(|a| a == Action::Pop(0)));
+        assert!(!board.legal_actions().any(|a| a == Action::Pop(1)));
+        let old = board;
+        let undo = board.play_action(Action::Pop(0));
+        assert_eq!(board.board.get(0, 0), 2);
+        assert_eq!(board.board.get(0, 1), 1);
+        assert_eq!(board.board.heights[0], 2);
+        board.reverse_last_action(undo);
+        assert_eq!(board.uid(), old.uid());
+        assert_eq!(board.board.heights, old.board.heights);
+        assert_eq!(board.history, old.history);
+    }
+
+    #[test]
+    fn full_after_pop() {
+        let mut board = PopOut::new();
+        for action in [Action::Drop(0), Action::Drop(1), Action::Pop(0)] {
+            board.play_action(action);
+        }
+        // fills the board without a line, the pop makes it take 44 moves.
+        for x in [
+            3, 3, 3, 3, 3, 3, 2, 2, 2, 2, 2, 2, 1, 4, 4, 4, 4, 4, 4, 1, 1, 1, 1, 5, 5, 5, 5, 5, 5,
+            0, 0, 0, 0, 0, 0, 6, 6, 6, 6, 6, 6,
+        ] {
+            assert!(!board.board.is_full());
+            assert_eq!(board.game_state(), GameState::InProgress);
+            board.play_action(Action::Drop(x));
+        }
+        assert_eq!(board.length(), 44);
+        assert!(board.board.is_full());
+        assert_eq!(board.game_state(), GameState::InProgress);
+        let actions: Vec<_> = board.legal_actions().collect();
+        assert!(!actions.is_empty());
+        assert!(actions.iter().all(|a| matches!(a, Action::Pop(_))));
+    }
+
+    #[test]
+    fn popper_wins_when_both_get_four() {
+        let mut board = PopOut::new();
+        // column 0 is red, yellow, red from the bottom, the rest of the bottom row is yellow
+        // and the rest of the second row is red.
+        for (x, y, v) in [(0, 0, 1), (0, 1, 2), (0, 2, 1)] {
+            board.board.set(x, y, v);
+        }
+        for x in 1..4 {
+            board.board.set(x, 0, 2);
+            board.board.set(x, 1, 1);
+        }
+        board.play_action(Action::Pop(0));
+        assert_eq!(board.game_state(), GameState::Won(Player::Red));
+    }
+
+    #[test]
+    fn repetition_is_a_draw() {
+        let mut board = PopOut::new();
+        let cycle = [
+            Action::Drop(0),
+            Action::Drop(1),
+            Action::Pop(0),
+            Action::Pop(1),
+        ];
+        // the empty board occurs for the second time after the first cycle.
+        for a in cycle.iter().chain(&cycle[..3]) {
+            board.play_action(*a);
+            assert_eq!(board.game_state(), GameState::InProgress);
+        }
+        board.play_action(Action::Pop(1));
+        assert_eq!(board.game_state(), GameState::Draw);
+    }
+
+    #[test]
+    fn key_follows_pops() {
+        let mut board = PopOut::new();
+        let mut undos = Vec::new();
+        for a in [0, 0, 0, 1, 1, 0] {
+            undos.push(board.play_action(Action::Drop(a)));
+        }
+        for a in [Action::Pop(0), Action::Pop(1)] {
+            undos.push(board.play_action(a));
+            let mut fresh = board.board;
+            fresh.update_key();
+            assert_eq!(board.key(), fresh.key);
+        }
+        while let Some(undo) = undos.pop() {
+            board.reverse_last_action(undo);
+        }
+        assert_eq!(board.key(), 0);
+    }
+}
@@ -1,122 +1,446 @@
+use crate::games::notation::Notation;
+use crate::games::threats::Threats;
 use crate::games::Game;
-use crate::games::{GameState, Player};
+use crate::games::{
+    center_out, move_key, player_to_move, position_key, position_state, sort_by_priority,
+    GameState, MoveList, Player, PositionError, Rules, WinCondition, MAX_LINE,
+};
 use crate::matchmaker::PlayableGame;
 use serde::{Deserialize, Serialize};
-use smallvec::SmallVec;
 use std::fmt;
 use std::io::BufRead;
 
 const BOARD_SIZE: usize = 8;
 
+// The standard game, an 8x8 board where four in a row wins.
+pub type Stack4 = StackN<BOARD_SIZE, BOARD_SIZE, 4>;
+
 type Action = (usize, usize);
 
+// The four directions of a line. Only one of the two opposite directions is listed.
+const LINE_DIRECTIONS: [Direction; 4] = [
+    Direction::East,
+    Direction::North,
+    Direction::NorthEast,
+    Direction::NorthWest,
+];
+
+#[derive(Clone, Copy)]
+enum Direction {
+    East,
+    West,
+    North,
+    South,
+    NorthEast,
+    SouthWest,
+    NorthWest,
+    SouthEast,
+}
+
+impl Direction {
+    fn opposite(self) -> Direction {
+        match self {
+            Direction::East => Direction::West,
+            Direction::West => Direction::East,
+            Direction::North => Direction::South,
+            Direction::South => Direction::North,
+            Direction::NorthEast => Direction::SouthWest,
+            Direction::SouthWest => Direction::NorthEast,
+            Direction::NorthWest => Direction::SouthEast,
+            Direction::SouthEast => Direction::NorthWest,
+        }
+    }
+}
+
+// Pieces are placed on a W wide and H high board, either at an edge or next to a piece
+// that is stacked all the way from an edge. First player to get K in a row wins unless other
+// rules are given with 'with_rules'. W * H must be at most 64.
 #[derive(Clone, Copy, Serialize, Deserialize)]
-pub struct Stack4 {
-    // tile on board takes up 2 bits, 0 for empty, 1 for red, 2 for yellow.
-    // starts in bottom left corner and goes row by row.
-    pub board: u128,
+pub struct StackN<const W: usize, const H: usize, const K: usize> {
+    // One bitboard per player, see FULL_MASK.
+    pub red: u64,
+    pub yellow: u64,
     pub cur_player: Player,
     pub game_state: GameState,
     pub nb_moves: u32,
+    pub rules: Rules,
+    // Zobrist key, see Game::key.
+    pub key: u64,
+    // Number of lines of rules.line_length tiles without any opponent piece, indexed by Player
+    // as usize - 1. Kept up to date by play_action, when both are 0 the game is a draw.
+    live_lines: [u16; 2],
 }
 
-impl Stack4 {
-    pub fn player_won(&self, piece_pos: [usize; 2]) -> bool {
-        let directions: [[i32; 2]; 4] = [[1, 0], [0, 1], [-1, 1], [1, 1]];
-        let player = self.get(piece_pos[0], piece_pos[1]);
-        for direction in directions {
-            let mut sm = 1;
-            for i in 1..4 {
-                let curx = direction[0] * i + piece_pos[0] as i32;
-                let cury = direction[1] * i + piece_pos[1] as i32;
-                if !Stack4::in_board(curx, cury) {
-                    break;
-                } else if player as u8 != self.get(curx as usize, cury as usize) {
-                    break;
+const fn column_mask(x: usize, width: usize, height: usize) -> u64 {
+    let mut mask = 0;
+    let mut y = 0;
+    while y < height {
+        mask |= 1 << (x + y * width);
+        y += 1;
+    }
+    mask
+}
+
+impl<const W: usize, const H: usize, const K: usize> StackN<W, H, K> {
+    const NB_TILES: usize = W * H;
+
+    // Bitboards have one bit per tile, row by row starting in the bottom left corner.
+    const FULL_MASK: u64 = if Self::NB_TILES == 64 {
+        u64::MAX
+    } else {
+        (1 << Self::NB_TILES) - 1
+    };
+    const FIRST_COLUMN: u64 = column_mask(0, W, H);
+    const LAST_COLUMN: u64 = column_mask(W - 1, W, H);
+    const FIRST_ROW: u64 = (1 << W) - 1;
+
+    const VALID_DIMENSIONS: () = assert!(
+        W * H <= 64 && W >= 2 && H >= 2 && K >= 2 && K <= MAX_LINE,
+        "board does not fit in the bitboards"
+    );
+
+    fn bit(x: usize, y: usize) -> u64 {
+        1 << (x + y * W)
+    }
+
+    fn row_mask(y: usize) -> u64 {
+        Self::FIRST_ROW << (y * W)
+    }
+
+    // Moves every tile in 'tiles' one step in 'dir', tiles that leave the board are removed.
+    fn step(dir: Direction, tiles: u64) -> u64 {
+        match dir {
+            Direction::East => (tiles << 1) & !Self::FIRST_COLUMN,
+            Direction::West => (tiles >> 1) & !Self::LAST_COLUMN,
+            Direction::North => (tiles << W) & Self::FULL_MASK,
+            Direction::South => tiles >> W,
+            Direction::NorthEast => (tiles << (W + 1)) & !Self::FIRST_COLUMN & Self::FULL_MASK,
+            Direction::SouthWest => (tiles >> (W + 1)) & !Self::LAST_COLUMN,
+            Direction::NorthWest => (tiles << (W - 1)) & !Self::LAST_COLUMN & Self::FULL_MASK,
+            Direction::SouthEast => (tiles >> (W - 1)) & !Self::FIRST_COLUMN,
+        }
+    }
+
+    pub fn with_rules(rules: Rules) -> Self {
+        let mut board = Self::new();
+        board.rules = rules;
+        board.update_live_lines();
+        board
+    }
+
+    // Tiles in 'free' where a line of 'n' tiles in direction 'dir' starts and only goes
+    // through tiles in 'free'.
+    fn line_starts(free: u64, n: usize, dir: Direction) -> u64 {
+        let mut starts = free;
+        for _ in 1..n {
+            starts = free & Self::step(dir.opposite(), starts);
+        }
+        starts
+    }
+
+    // Number of lines through 'tile' that don't go through any of 'pieces'.
+    fn lines_through(&self, tile: u64, pieces: u64) -> u16 {
+        let n = self.rules.line_length;
+        let free = Self::FULL_MASK & !pieces;
+        LINE_DIRECTIONS
+            .iter()
+            .map(|&dir| {
+                let (mut starts, mut start) = (tile, tile);
+                for _ in 1..n {
+                    start = Self::step(dir.opposite(), start);
+                    starts |= start;
                 }
-                sm += 1;
+                (Self::line_starts(free, n, dir) & starts).count_ones() as u16
+            })
+            .sum()
+    }
+
+    // Recomputes live_lines after the board or the rules were changed other than by playing
+    // actions.
+    fn update_live_lines(&mut self) {
+        let n = self.rules.line_length;
+        for player in [Player::Red, Player::Yellow] {
+            let free = Self::FULL_MASK & !self.pieces(!player);
+            self.live_lines[player as usize - 1] = LINE_DIRECTIONS
+                .iter()
+                .map(|&dir| Self::line_starts(free, n, dir).count_ones() as u16)
+                .sum();
+        }
+    }
+
+    // True if no player can get a line anymore.
+    pub fn is_dead(&self) -> bool {
+        self.live_lines == [0, 0]
+    }
+
+    // Builds a position from 'cells' given column by column from the bottom left corner where
+    // 0 is empty, 1 is red and 2 is yellow. The player to move, number of moves and game state
+    // are computed from the pieces, 'cur_player' is only checked against them.
+    pub fn from_cells(cells: &[u8], cur_player: Option<Player>) -> Result<Self, PositionError> {
+        if cells.len() != Self::NB_TILES {
+            return Err(PositionError::WrongSize {
+                expected: Self::NB_TILES,
+                found: cells.len(),
+            });
+        }
+        let mut board = Self::new();
+        for (i, &value) in cells.iter().enumerate() {
+            let (x, y) = (i / H, i % H);
+            match value {
+                0 => {}
+                1 => board.red |= Self::bit(x, y),
+                2 => board.yellow |= Self::bit(x, y),
+                _ => return Err(PositionError::InvalidCell { x, y, value }),
             }
-            for i in 1..4 {
-                let i = -i;
-                let curx = direction[0] * i + piece_pos[0] as i32;
-                let cury = direction[1] * i + piece_pos[1] as i32;
-                if !Stack4::in_board(curx, cury) {
-                    break;
-                } else if player as u8 != self.get(curx as usize, cury as usize) {
-                    break;
-                }
-                sm += 1;
+        }
+        let occupied = board.red | board.yellow;
+        let floating = occupied & !Self::supported(occupied);
+        if floating != 0 {
+            let i = floating.trailing_zeros() as usize;
+            return Err(PositionError::FloatingPiece { x: i % W, y: i / W });
+        }
+        let red = board.red.count_ones();
+        let yellow = board.yellow.count_ones();
+        board.cur_player = player_to_move(red, yellow, cur_player)?;
+        board.nb_moves = red + yellow;
+        board.update_live_lines();
+        board.game_state = position_state(
+            [board.has_line(board.red), board.has_line(board.yellow)],
+            board.is_full() || board.is_dead(),
+            board.cur_player,
+            &board.rules,
+        )?;
+        board.update_key();
+        Ok(board)
+    }
+
+    // Tiles in 'occupied' where every tile between it and one of the edges is occupied.
+    fn supported(occupied: u64) -> u64 {
+        let edges = [
+            (Self::FIRST_COLUMN, Direction::East),
+            (Self::LAST_COLUMN, Direction::West),
+            (Self::row_mask(0), Direction::North),
+            (Self::row_mask(H - 1), Direction::South),
+        ];
+        let mut supported = 0;
+        for (edge, dir) in edges {
+            let mut reached = occupied & edge;
+            for _ in 1..W.max(H) {
+                reached |= Self::step(dir, reached) & occupied;
             }
-            if sm >= 4 {
-                return true;
+            supported |= reached;
+        }
+        supported
+    }
+
+    // Returns [run_0, run_1, .., run_len-1] where run_n are the tiles whose 'n' closest
+    // neighbours in the opposite direction of 'dir' all are in 'pieces'.
+    fn runs(dir: Direction, pieces: u64, len: usize) -> [u64; MAX_LINE + 1] {
+        let mut runs = [Self::FULL_MASK; MAX_LINE + 1];
+        let mut run = Self::FULL_MASK;
+        for r in runs.iter_mut().take(len).skip(1) {
+            run = Self::step(dir, run & pieces);
+            *r = run;
+        }
+        runs
+    }
+
+    // Returns true if there is a line in 'pieces' that counts under self.rules.
+    fn has_line(&self, pieces: u64) -> bool {
+        let n = self.rules.line_length;
+        LINE_DIRECTIONS.iter().any(|&dir| {
+            let runs = Self::runs(dir, pieces, n + 1);
+            let mut ends = runs[n - 1] & pieces;
+            if self.rules.win_condition == WinCondition::Exact {
+                // the tiles just before and after the line must not be in 'pieces'.
+                ends &= !runs[n] & !Self::step(dir.opposite(), pieces);
+            }
+            ends != 0
+        })
+    }
+
+    // Returns all tiles, empty or not, that would complete a line of length 'n' for 'pieces'.
+    fn line_tiles(n: usize, pieces: u64) -> u64 {
+        let mut tiles = 0;
+        for dir in LINE_DIRECTIONS {
+            let before = Self::runs(dir, pieces, n);
+            let after = Self::runs(dir.opposite(), pieces, n);
+            for i in 0..n {
+                tiles |= before[i] & after[n - 1 - i];
+            }
+        }
+        tiles
+    }
+
+    // Returns the tiles in 'tiles' that would give 'pieces' a line that counts.
+    fn completing_tiles(&self, pieces: u64, tiles: u64) -> u64 {
+        let mut completing = tiles & Self::line_tiles(self.rules.line_length, pieces);
+        if self.rules.win_condition == WinCondition::Exact {
+            // the line might get too long so every tile has to be checked.
+            let mut rest = completing;
+            while rest != 0 {
+                let tile = rest & rest.wrapping_neg();
+                rest &= rest - 1;
+                if !self.has_line(pieces | tile) {
+                    completing &= !tile;
+                }
             }
         }
-        false
+        completing
     }
 
+    // Mirrors the board so that tile (x, y) ends up at (W-1-x, H-1-y).
+    fn reverse(tiles: u64) -> u64 {
+        tiles.reverse_bits() >> (64 - Self::NB_TILES)
+    }
+
+    // Empty tiles where every tile between it and the left edge is occupied.
+    fn west_frontier(occupied: u64) -> u64 {
+        ((occupied & !Self::LAST_COLUMN) + Self::FIRST_COLUMN) & !occupied & Self::FULL_MASK
+    }
+
+    // Empty tiles where every tile between it and the bottom edge is occupied.
+    fn south_frontier(occupied: u64) -> u64 {
+        let empty = !occupied & Self::FULL_MASK;
+        // every tile that has an empty tile somewhere below it.
+        let mut below = empty << W;
+        let mut shift = W;
+        while shift < Self::NB_TILES {
+            below |= below << shift;
+            shift *= 2;
+        }
+        empty & !below
+    }
+
+    // Returns true if the player who owns the piece at 'piece_pos' has won under self.rules.
+    pub fn player_won(&self, piece_pos: [usize; 2]) -> bool {
+        let player = match self.get(piece_pos[0], piece_pos[1]) {
+            1 => Player::Red,
+            2 => Player::Yellow,
+            _ => return false,
+        };
+        [player, !player]
+            .into_iter()
+            .any(|p| self.rules.line_winner(p) == player && self.has_line(self.pieces(p)))
+    }
+
+    // The squares that would give either player a line, on this board's bitboard layout.
+    // Pieces don't fall so there are no odd, even or stacked threats.
+    pub fn threats(&self) -> Threats {
+        let empty = Self::FULL_MASK & !(self.red | self.yellow);
+        let squares = [Player::Red, Player::Yellow]
+            .map(|player| self.completing_tiles(self.pieces(player), empty) as u128);
+        Threats::new(squares, self.legal_tiles() as u128, 0, |_| 0)
+    }
+
+    // Returns true if 'player' would win by placing a piece at 'action'.
     pub fn is_winning_action(&self, action: Action, player: Player) -> bool {
-        let mut resulting_board = self.clone();
-        resulting_board.play_action(action);
-        resulting_board.game_state == GameState::Won(player)
+        self.rules.line_winner(player) == player
+            && self.has_line(self.pieces(player) | Self::bit(action.0, action.1))
     }
 
     pub fn is_full(&self) -> bool {
-        let mut yellow_mask: u128 = 2;
-        for _ in 0..64 {
-            yellow_mask <<= 2;
-            yellow_mask += 2;
+        self.red | self.yellow == Self::FULL_MASK
+    }
+
+    pub fn in_board(x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && x < W as i32 && y < H as i32
+    }
+
+    pub fn pieces(&self, player: Player) -> u64 {
+        match player {
+            Player::Red => self.red,
+            Player::Yellow => self.yellow,
         }
-        let mut red_mask: u128 = 1;
-        for _ in 0..64 {
-            red_mask <<= 2;
-            red_mask += 1;
+    }
+
+    fn pieces_mut(&mut self, player: Player) -> &mut u64 {
+        match player {
+            Player::Red => &mut self.red,
+            Player::Yellow => &mut self.yellow,
         }
-        yellow_mask == (self.board & yellow_mask | (self.board & red_mask) << 1)
-        // !( ((self.board >> 1)|self.board) |  )==0
-        //self.legal_actions().count() == 0
     }
 
-    pub fn in_board(x: i32, y: i32) -> bool {
-        x >= 0 && y >= 0 && x < BOARD_SIZE as i32 && y < BOARD_SIZE as i32
+    // Bitboard of all empty tiles that can be reached from one of the four edges.
+    pub fn legal_tiles(&self) -> u64 {
+        let occupied = self.red | self.yellow;
+        Self::west_frontier(occupied)
+            | Self::reverse(Self::west_frontier(Self::reverse(occupied)))
+            | Self::south_frontier(occupied)
+            | Self::reverse(Self::south_frontier(Self::reverse(occupied)))
     }
 
     pub fn set(&mut self, x: usize, y: usize, v: u8) {
-        let k = 2 * (x + y * BOARD_SIZE);
-        let mask = 3 << k;
-        self.board = (self.board & (!mask)) + ((v as u128) << k);
+        let b = Self::bit(x, y);
+        self.red &= !b;
+        self.yellow &= !b;
+        match v {
+            1 => self.red |= b,
+            2 => self.yellow |= b,
+            _ => {}
+        }
+        self.update_key();
+        self.update_live_lines();
+    }
+
+    // Recomputes the Zobrist key after the board was changed other than by playing actions.
+    fn update_key(&mut self) {
+        self.key = position_key(self.red as u128, self.yellow as u128, self.cur_player);
     }
 
     pub fn get(&self, x: usize, y: usize) -> u8 {
-        3 & (self.board >> (2 * (x + y * BOARD_SIZE))) as u8
+        let b = Self::bit(x, y);
+        if self.red & b != 0 {
+            1
+        } else if self.yellow & b != 0 {
+            2
+        } else {
+            0
+        }
     }
 
-    // Returns board rotated by 90*n degrees
-    fn rotation(&self, n: u32) -> Self {
-        let n = n % 4;
-        let mut new_board = Stack4::new();
-        for x in 0..BOARD_SIZE as i32 {
-            for y in 0..BOARD_SIZE as i32 {
-                let (nx, ny) = Self::rotate(x, y, n);
-                new_board.set(nx as usize, ny as usize, self.get(x as usize, y as usize))
+    // Applies 'f' to the coordinates of every piece on the board.
+    fn transform<F: Fn(i32, i32) -> (i32, i32)>(&self, f: F) -> Self {
+        let move_pieces = |pieces: u64| {
+            let mut moved = 0;
+            let mut rest = pieces;
+            while rest != 0 {
+                let i = rest.trailing_zeros() as usize;
+                rest &= rest - 1;
+                let (nx, ny) = f((i % W) as i32, (i / W) as i32);
+                moved |= Self::bit(nx as usize, ny as usize);
             }
-        }
-        Stack4 {
-            board: new_board.board,
-            cur_player: self.cur_player,
-            game_state: self.game_state,
-            nb_moves: self.nb_moves,
-        }
+            moved
+        };
+        let mut transformed = StackN {
+            red: move_pieces(self.red),
+            yellow: move_pieces(self.yellow),
+            ..*self
+        };
+        transformed.update_key();
+        transformed
+    }
+
+    // Returns board rotated by 90*n degrees, n must be even if the board isn't square.
+    fn rotation(&self, n: u32) -> Self {
+        self.transform(|x, y| Self::rotate(x, y, n))
     }
 
     // rotates a point 90*n degrees around the center of the board.
-    // center is located at (3.5, 3.5)
+    // center is located at ((W-1)/2, (H-1)/2)
     fn rotate(x: i32, y: i32, n: u32) -> (i32, i32) {
         let n = n % 4;
+        let mx = W as i32 - 1;
+        let my = H as i32 - 1;
+        assert!(
+            W == H || n == 0 || n == 2,
+            "can only rotate square boards by 90 degrees"
+        );
         match n {
             0 => (x, y),
-            1 => (-y + 7, x),
-            2 => (-x + 7, -y + 7),
-            3 => (y, -x + 7),
+            1 => (-y + my, x),
+            2 => (-x + mx, -y + my),
+            3 => (y, -x + mx),
             _ => {
                 panic!("Impossible!")
             }
@@ -124,61 +448,62 @@ impl Stack4 {
     }
 
     // mirrors board around the middle of the board.
-    // assumes BOARD_SIZE = 8. so if BOARD_WIDTH changes then so must this function
     pub fn mirror(&self) -> Self {
-        let mut col_mask: u128 = 0;
-        for _ in 0..BOARD_SIZE {
-            col_mask = (col_mask << BOARD_SIZE * 2) + 3;
-        }
-        let mut new_board: u128 = 0;
-        for i in 0..BOARD_SIZE / 2 {
-            new_board += (self.board & (col_mask << (i * 2))) << ((BOARD_SIZE / 2 - i) * 4 - 2);
-        }
-        for i in 0..BOARD_SIZE / 2 {
-            new_board += (self.board & (col_mask << (BOARD_SIZE / 2 + i) * 2)) >> (i) * 4 + 2;
-        }
-
-        Stack4 {
-            board: new_board,
-            cur_player: self.cur_player,
-            game_state: self.game_state,
-            nb_moves: self.nb_moves,
-        }
+        self.transform(|x, y| (W as i32 - 1 - x, y))
     }
 }
 
-impl Game for Stack4 {
+impl<const W: usize, const H: usize, const K: usize> Game for StackN<W, H, K> {
     type Action = (usize, usize); // x,y coordinates of the placed piece.
+    type Undo = Self::Action;
+    type Uid = u128;
 
     fn new() -> Self {
-        Self {
-            board: 0,
+        let () = Self::VALID_DIMENSIONS;
+        let mut board = Self {
+            red: 0,
+            yellow: 0,
             cur_player: Player::Red,
             game_state: GameState::InProgress,
             nb_moves: 0,
-        }
+            key: 0,
+            rules: Rules::new(WinCondition::Standard, K),
+            live_lines: [0; 2],
+        };
+        board.update_live_lines();
+        board
     }
 
     // Assumes that 'action' is a legal action.
-    fn play_action(&mut self, action: Self::Action) {
+    fn play_action(&mut self, action: Self::Action) -> Self::Action {
         assert_eq!(self.game_state, GameState::InProgress);
-        self.set(action.0, action.1, self.cur_player as u8);
+        let player = self.cur_player;
+        let tile = Self::bit(action.0, action.1);
+        // the opponent can't complete the lines through the tile anymore.
+        self.live_lines[!player as usize - 1] -= self.lines_through(tile, self.pieces(player));
+        *self.pieces_mut(player) |= tile;
+        self.key ^= move_key(player, action.0 + action.1 * W);
         self.nb_moves += 1;
 
-        if self.player_won([action.0, action.1]) {
-            self.game_state = GameState::Won(self.cur_player);
-        } else if self.is_full() {
+        if self.has_line(self.pieces(player)) {
+            self.game_state = GameState::Won(self.rules.line_winner(player));
+        } else if self.is_full() || self.is_dead() {
             self.game_state = GameState::Draw;
         } else {
             self.game_state = GameState::InProgress;
         }
-        self.cur_player = !self.cur_player;
+        self.cur_player = !player;
+        action
     }
 
     fn reverse_last_action(&mut self, last_action: Self::Action) {
-        self.set(last_action.0, last_action.1, 0);
+        let player = !self.cur_player;
+        let tile = Self::bit(last_action.0, last_action.1);
+        *self.pieces_mut(player) &= !tile;
+        self.key ^= move_key(player, last_action.0 + last_action.1 * W);
+        self.live_lines[!player as usize - 1] += self.lines_through(tile, self.pieces(player));
         self.game_state = GameState::InProgress;
-        self.cur_player = !self.cur_player;
+        self.cur_player = player;
         self.nb_moves -= 1;
     }
 
@@ -190,87 +515,125 @@ impl Game for Stack4 {
         self.cur_player
     }
 
-    fn legal_actions(&self) -> Box<dyn Iterator<Item = Action>> {
-        let dirs = [[1, 0], [0, 1], [-1, 0], [0, -1]];
-        let starts = [
-            [0, 0],
-            [BOARD_SIZE - 1, 0],
-            [BOARD_SIZE - 1, BOARD_SIZE - 1],
-            [0, BOARD_SIZE - 1],
-        ];
-        let mut prev_actions: u64 = 0;
-        let mut winning_moves = SmallVec::<[Action; BOARD_SIZE * 4]>::new();
-
-        let mut blocking_moves = SmallVec::<[Action; BOARD_SIZE * 4]>::new();
-
-        let move_order = [3, 4, 2, 5, 1, 6, 0, 7];
-
-        let mut actions = SmallVec::<[Action; BOARD_SIZE * 4]>::new();
-        for c in move_order {
-            for (dir, start) in dirs.iter().zip(starts) {
-                let inward_direction = [-dir[1], dir[0]];
-                let cur_start = [
-                    start[0] as i32 + dir[0] as i32 * c,
-                    start[1] as i32 + dir[1] as i32 * c,
-                ];
-                for k in 0..BOARD_SIZE {
-                    let cur_cord = [
-                        (cur_start[0] + k as i32 * inward_direction[0]) as usize,
-                        (cur_start[1] + k as i32 * inward_direction[1]) as usize,
-                    ];
-                    // 0 represents TileStates::Empty
-                    if self.get(cur_cord[0], cur_cord[1]) == 0 {
-                        if prev_actions >> (cur_cord[0] + cur_cord[1] * BOARD_SIZE) & 1 == 0 {
-                            if self.is_winning_action((cur_cord[0], cur_cord[1]), self.cur_player) {
-                                winning_moves.push((cur_cord[0], cur_cord[1]))
-                            } else if self
-                                .is_winning_action((cur_cord[0], cur_cord[1]), !self.cur_player)
-                            {
-                                blocking_moves.push((cur_cord[0], cur_cord[1]))
-                            } else {
-                                actions.push((cur_cord[0], cur_cord[1]));
-                            }
-                            prev_actions += 1 << (cur_cord[0] + cur_cord[1] * BOARD_SIZE);
-                        }
-                        break;
-                    }
+    // Lines close to the middle of each edge come first.
+    fn generate_moves(&self, moves: &mut MoveList<Action>) {
+        if self.game_state != GameState::InProgress {
+            return;
+        }
+        let mut remaining = self.legal_tiles();
+        for i in 0..W.max(H) {
+            let mut lines = [0; 4];
+            if i < W {
+                let c = center_out(W, i);
+                lines[0] = column_mask(c, W, H);
+                lines[2] = column_mask(W - 1 - c, W, H);
+            }
+            if i < H {
+                let r = center_out(H, i);
+                lines[1] = Self::row_mask(r);
+                lines[3] = Self::row_mask(H - 1 - r);
+            }
+            for line in lines {
+                let mut tiles = remaining & line;
+                while tiles != 0 {
+                    let i = tiles.trailing_zeros() as usize;
+                    tiles &= tiles - 1;
+                    remaining &= !(1 << i);
+                    moves.push((i % W, i / W));
                 }
             }
         }
-        Box::new(
-            winning_moves
-                .into_iter()
-                .chain(blocking_moves.into_iter())
-                .chain(actions.into_iter()),
-        )
+    }
+
+    // Winning moves first, then moves that block the opponent from winning next turn. In misere
+    // the moves that make a line lose and are put last instead.
+    fn order_moves(&self, moves: &mut MoveList<Action>) {
+        let threats = self.threats();
+        let misere = self.rules.win_condition == WinCondition::Misere;
+        let completing = threats.immediate(self.cur_player);
+        let (winning, losing) = if misere {
+            (0, completing)
+        } else {
+            (completing, 0)
+        };
+        let blocking = if misere {
+            0
+        } else {
+            threats.immediate(!self.cur_player)
+        };
+        sort_by_priority(moves, |(x, y)| {
+            let b = 1 << (x + y * W);
+            if winning & b != 0 {
+                0
+            } else if blocking & b != 0 {
+                1
+            } else if losing & b != 0 {
+                3
+            } else {
+                2
+            }
+        });
     }
 
     fn vectorize(&self, player: Player) -> Vec<f64> {
-        let mut v = Vec::with_capacity(BOARD_SIZE * BOARD_SIZE);
-        let mut board = self.board;
-        for _ in 0..BOARD_SIZE {
-            for _ in 0..BOARD_SIZE {
-                let cur = board as u8 & 3;
-                if cur == player as u8 {
-                    v.push(1.0);
-                } else if cur == !player as u8 {
-                    v.push(-1.0);
-                } else {
-                    v.push(0.0);
-                }
-                board >>= 2;
+        let own = self.pieces(player);
+        let opponent = self.pieces(!player);
+        let mut v = Vec::with_capacity(Self::NB_TILES);
+        for i in 0..Self::NB_TILES {
+            if own >> i & 1 == 1 {
+                v.push(1.0);
+            } else if opponent >> i & 1 == 1 {
+                v.push(-1.0);
+            } else {
+                v.push(0.0);
             }
         }
         v
     }
 
     fn shape() -> [usize; 2] {
-        [BOARD_SIZE, BOARD_SIZE]
+        [W, H]
+    }
+
+    // Squares row by row from the bottom left corner, the same order as the bitboards.
+    fn action_count() -> usize {
+        W * H
+    }
+
+    fn action_to_index(action: Action) -> usize {
+        action.0 + action.1 * W
+    }
+
+    fn index_to_action(index: usize) -> Action {
+        (index % W, index / W)
+    }
+
+    fn legal_mask(&self) -> u128 {
+        if self.game_state != GameState::InProgress {
+            return 0;
+        }
+        self.legal_tiles() as u128
+    }
+
+    // Squares are numbered like the tiles of the bitboards.
+    fn legal_squares(&self) -> u128 {
+        self.legal_mask()
+    }
+
+    fn threat_squares(&self, player: Player) -> u128 {
+        self.threats().squares(player)
     }
 
+    fn live_lines(&self, player: Player) -> Option<u32> {
+        Some(self.live_lines[player as usize - 1] as u32)
+    }
+
+    // Square boards have 8 symmetries, rectangular boards can't be rotated by 90 degrees
+    // so they only have 4.
     fn symmetries(&self) -> Vec<Self> {
         let mut symmetries = Vec::with_capacity(8);
-        for n in 0..4 {
+        let step = if W == H { 1 } else { 2 };
+        for n in (0..4).step_by(step) {
             let rotated = self.rotation(n);
             symmetries.push(rotated);
             symmetries.push(rotated.mirror());
@@ -278,8 +641,37 @@ impl Game for Stack4 {
         symmetries
     }
 
+    // Symmetry 2n is a rotation by 90*n degrees, or 180*n degrees if the board isn't square,
+    // and 2n+1 is the same rotation followed by a mirror.
+    fn transform_action(&self, action: Action, symmetry: usize) -> Action {
+        let n = if W == H {
+            symmetry / 2
+        } else {
+            symmetry / 2 * 2
+        };
+        let (x, y) = Self::rotate(action.0 as i32, action.1 as i32, n as u32);
+        let x = if symmetry % 2 == 1 {
+            W as i32 - 1 - x
+        } else {
+            x
+        };
+        (x as usize, y as usize)
+    }
+
+    fn inverse_symmetry(&self, symmetry: usize) -> usize {
+        if W == H && symmetry % 2 == 0 {
+            (8 - symmetry) % 8
+        } else {
+            symmetry
+        }
+    }
+
     fn uid(&self) -> u128 {
-        self.board
+        (self.yellow as u128) << 64 | self.red as u128
+    }
+
+    fn key(&self) -> u64 {
+        self.key
     }
 
     fn length(&self) -> u32 {
@@ -287,12 +679,12 @@ impl Game for Stack4 {
     }
 }
 
-impl fmt::Debug for Stack4 {
+impl<const W: usize, const H: usize, const K: usize> fmt::Debug for StackN<W, H, K> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut s = String::new();
         let legal_actions: Vec<_> = self.legal_actions().collect();
-        for y in (0..BOARD_SIZE).rev() {
-            for x in 0..BOARD_SIZE {
+        for y in (0..H).rev() {
+            for x in 0..W {
                 match self.get(x, y) {
                     0 => {
                         if legal_actions.iter().any(|c| *c == (x, y)) {
@@ -315,45 +707,70 @@ impl fmt::Debug for Stack4 {
     }
 }
 
-impl PlayableGame for Stack4 {
+impl<const W: usize, const H: usize, const K: usize> PlayableGame for StackN<W, H, K> {
     // returns (action, is_reverse)
     fn get_action_from_user(&self) -> (Action, bool) {
+        let threats = self.threats().immediate(!self.cur_player);
+        if threats != 0 && self.rules.win_condition != WinCondition::Misere {
+            let mut squares = String::new();
+            for i in (0..Self::NB_TILES).filter(|i| threats >> i & 1 == 1) {
+                squares.push(' ');
+                Self::write_action((i % W, i / W), &mut squares);
+            }
+            println!("{:?} threatens{}", !self.cur_player, squares);
+        }
         let stdin = std::io::stdin();
         let legal_actions: Vec<_> = self.legal_actions().collect();
 
-        fn parse_cord(s: &str) -> Option<(usize, usize)> {
-            let mut numbers = s.split(',');
-            let x = numbers.next()?.parse::<usize>().ok()?;
-            let y = numbers.next()?.parse::<usize>().ok()?;
-            Some((x, y))
-        }
-
         for line in stdin.lock().lines() {
             let line = line.unwrap();
-            if line.as_bytes()[0] == 'z' as u8 {
-                return ((0, 0), true);
-            } else if let Some((x, y)) = parse_cord(&line) {
-                if x < BOARD_SIZE && y < BOARD_SIZE {
-                    if !legal_actions.iter().any(|c| *c == (x, y)) {
+            let line = line.trim();
+            match Self::read_action(line) {
+                Some((action, len)) if len == line.len() => {
+                    if !legal_actions.contains(&action) {
                         println!("Illegal action");
                         continue;
                     }
-                    return ((x, y), false);
-                } else {
-                    println!("Not in range (0..{}, 0..{})", BOARD_SIZE, BOARD_SIZE);
+                    return (action, false);
                 }
-            } else {
-                println!("Invalid input: try again");
+                _ if line == "z" => return ((0, 0), true),
+                _ => println!("Invalid input: give a square like a1"),
             }
         }
         panic!("Failed to get input from user");
     }
 }
 
+// Squares are written as a column letter and a 1-based row number, a1 is the bottom left
+// corner. Only boards up to 26 columns wide can be written.
+impl<const W: usize, const H: usize, const K: usize> Notation for StackN<W, H, K> {
+    fn read_action(s: &str) -> Option<(Action, usize)> {
+        let bytes = s.as_bytes();
+        let x = bytes.first()?.checked_sub(b'a')? as usize;
+        let digits = bytes[1..].iter().take_while(|b| b.is_ascii_digit()).count();
+        // sliced as bytes since the first character doesn't have to be ASCII.
+        let y = std::str::from_utf8(&bytes[1..1 + digits])
+            .ok()?
+            .parse::<usize>()
+            .ok()?
+            .checked_sub(1)?;
+        if x < W.min(26) && y < H {
+            Some(((x, y), 1 + digits))
+        } else {
+            None
+        }
+    }
+
+    fn write_action(action: Action, s: &mut String) {
+        s.push((b'a' + action.0 as u8) as char);
+        s.push_str(&(action.1 + 1).to_string());
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Stack4;
-    use crate::games::{Game, GameState};
+    use super::{Stack4, StackN, BOARD_SIZE};
+    use crate::games::{Game, GameState, Player};
     #[test]
     fn draw() {
         let actions = vec![
@@ -422,16 +839,26 @@ mod tests {
             (7, 3),
             (7, 4),
         ];
+        // no line can be completed anymore with two tiles left.
         let mut board = Stack4::new();
-        for action in actions {
+        for &action in &actions {
+            if board.game_state() != GameState::InProgress {
+                break;
+            }
             board.play_action(action);
         }
         println!("{:?}", board);
         assert_eq!(board.game_state(), GameState::Draw);
+        assert!(board.is_dead() && !board.is_full());
+        assert_eq!(board.nb_moves, 62);
 
+        // board in the old two bits per tile encoding, 0 for empty, 1 for red, 2 for yellow.
+        let old_board: u128 = 120182736557749463504389418626142590566;
         let mut board = Stack4::new();
         board.nb_moves = 64;
-        board.board = 120182736557749463504389418626142590566;
+        for i in 0..64 {
+            board.set(i % 8, i / 8, (old_board >> (2 * i)) as u8 & 3);
+        }
         board.reverse_last_action((0, 0));
         println!("{:?}", board);
         assert_eq!(board.game_state(), GameState::InProgress);
@@ -442,4 +869,223 @@ mod tests {
         assert!(board.is_full());
         assert_ne!(board.game_state(), GameState::InProgress);
     }
+
+    #[test]
+    fn live_lines() {
+        assert_eq!(Stack4::new().live_lines(Player::Red), Some(130));
+        let rng = fastrand::Rng::with_seed(2);
+        for _ in 0..50 {
+            let mut board = Stack4::new();
+            let mut played = Vec::new();
+            while board.game_state() == GameState::InProgress {
+                let actions: Vec<_> = board.legal_actions().collect();
+                let action = actions[rng.usize(..actions.len())];
+                board.play_action(action);
+                played.push(action);
+                let mut counted = board;
+                counted.update_live_lines();
+                assert_eq!(board.live_lines, counted.live_lines);
+            }
+            for action in played.into_iter().rev() {
+                board.reverse_last_action(action);
+            }
+            assert_eq!(board.live_lines, Stack4::new().live_lines);
+        }
+    }
+
+    #[test]
+    fn legal_actions() {
+        let mut board = Stack4::new();
+        assert_eq!(board.legal_actions().count(), 28);
+        board.play_action((0, 0));
+        board.play_action((0, 1));
+        board.play_action((1, 1));
+        let mut actions: Vec<_> = board.legal_actions().collect();
+        actions.sort();
+        let mut expected = Vec::new();
+        for x in 0..8 {
+            for y in 0..8 {
+                let edge = x == 0 || y == 0 || x == 7 || y == 7;
+                if edge && board.get(x, y) == 0 || (x, y) == (2, 1) {
+                    expected.push((x, y));
+                }
+            }
+        }
+        assert_eq!(actions, expected);
+    }
+
+    #[test]
+    fn winning_and_blocking_moves_first() {
+        let mut board = Stack4::new();
+        // red threatens to win at (3, 0) and yellow at (7, 3).
+        for action in [(0, 0), (7, 0), (1, 0), (7, 1), (2, 0), (7, 2)] {
+            board.play_action(action);
+        }
+        let actions: Vec<_> = board.legal_actions().collect();
+        assert_eq!(actions[..2], [(3, 0), (7, 3)]);
+        assert!(board.is_winning_action((3, 0), Player::Red));
+        assert!(!board.is_winning_action((3, 0), Player::Yellow));
+        board.play_action((3, 0));
+        assert_eq!(board.game_state(), GameState::Won(Player::Red));
+    }
+
+    #[test]
+    fn symmetries() {
+        let mut board = Stack4::new();
+        for action in [(0, 0), (0, 1), (5, 7), (7, 3)] {
+            board.play_action(action);
+        }
+        let symmetries = board.symmetries();
+        assert_eq!(symmetries[0].uid(), board.uid());
+        assert_eq!(symmetries[1].get(7, 0), 1);
+        assert_eq!(symmetries[1].get(7, 1), 2);
+        assert_eq!(symmetries[2].get(7, 0), 1);
+        assert_eq!(symmetries[2].get(0, 5), 1);
+        for symmetry in symmetries {
+            assert_eq!(
+                symmetry.legal_actions().count(),
+                board.legal_actions().count()
+            );
+        }
+    }
+
+    #[test]
+    fn small_boards() {
+        // three in a row on a 4x4 board.
+        let mut board = StackN::<4, 4, 3>::new();
+        assert_eq!(board.legal_actions().count(), 12);
+        for action in [(0, 0), (3, 3), (1, 1), (3, 2)] {
+            board.play_action(action);
+        }
+        let actions: Vec<_> = board.legal_actions().collect();
+        assert_eq!(actions[..2], [(2, 2), (3, 1)]);
+        board.play_action((2, 2));
+        assert_eq!(board.game_state(), GameState::Won(Player::Red));
+
+        // rectangular boards only have four symmetries.
+        let mut board = StackN::<6, 4, 4>::new();
+        board.play_action((0, 0));
+        board.play_action((5, 1));
+        let symmetries = board.symmetries();
+        assert_eq!(symmetries.len(), 4);
+        assert_eq!(symmetries[1].get(5, 0), 1);
+        assert_eq!(symmetries[2].get(5, 3), 1);
+        assert_eq!(symmetries[2].get(0, 2), 2);
+        assert_eq!(symmetries[3].get(0, 3), 1);
+    }
+
+    #[test]
+    fn exact_rules() {
+        use crate::games::{Rules, WinCondition};
+        let mut board = Stack4::with_rules(Rules::new(WinCondition::Exact, 4));
+        // red plays the bottom row from both ends, yellow the row above.
+        for mv in [
+            (0, 0),
+            (0, 1),
+            (1, 0),
+            (1, 1),
+            (2, 0),
+            (2, 1),
+            (4, 0),
+            (7, 1),
+        ] {
+            board.play_action(mv);
+        }
+        assert!(!board.is_winning_action((3, 0), Player::Red));
+        assert!(board.is_winning_action((3, 1), Player::Yellow));
+        assert_eq!(board.legal_actions().next(), Some((3, 1)));
+        board.play_action((3, 0));
+        assert_eq!(board.game_state, GameState::InProgress);
+        board.play_action((3, 1));
+        assert_eq!(board.game_state, GameState::Won(Player::Yellow));
+    }
+
+    #[test]
+    fn transform_actions_and_canonical() {
+        let mut board = Stack4::new();
+        for action in [(0, 0), (0, 1), (5, 7), (7, 3)] {
+            board.play_action(action);
+        }
+        let symmetries = board.symmetries();
+        for (s, symmetry) in symmetries.iter().enumerate() {
+            for action in board.legal_actions() {
+                let mut played = board;
+                played.play_action(action);
+                let mut symmetry = *symmetry;
+                let transformed = board.transform_action(action, s);
+                symmetry.play_action(transformed);
+                assert_eq!(symmetry.uid(), played.symmetries()[s].uid());
+                assert_eq!(board.untransform_action(transformed, s), action);
+            }
+            assert_eq!(symmetry.canonical_uid(), board.canonical_uid());
+        }
+        let (canonical, s) = board.canonical();
+        assert_eq!(canonical.uid(), symmetries[s].uid());
+    }
+
+    #[test]
+    fn action_indices_and_masks() {
+        for i in 0..Stack4::action_count() {
+            assert_eq!(Stack4::action_to_index(Stack4::index_to_action(i)), i);
+        }
+        let mut board = Stack4::new();
+        for action in [(0, 0), (0, 1), (5, 7), (7, 3), (1, 0)] {
+            board.play_action(action);
+        }
+        let mask = board.legal_mask();
+        let expected = board
+            .legal_actions()
+            .fold(0, |m, a| m | 1 << Stack4::action_to_index(a));
+        assert_eq!(mask, expected);
+        assert_eq!(mask.count_ones() as usize, board.legal_actions().count());
+
+        for (s, symmetry) in board.symmetries().iter().enumerate() {
+            let transformed = board.legal_actions().fold(0, |m, a| {
+                m | 1 << Stack4::action_to_index(board.transform_action(a, s))
+            });
+            assert_eq!(symmetry.legal_mask(), transformed);
+        }
+    }
+
+    #[test]
+    fn from_cells() {
+        use crate::games::PositionError;
+        let mut board = Stack4::new();
+        for action in [(0, 0), (0, 1), (5, 7), (7, 3), (6, 7)] {
+            board.play_action(action);
+        }
+        let mut cells = vec![0; BOARD_SIZE * BOARD_SIZE];
+        for x in 0..BOARD_SIZE {
+            for y in 0..BOARD_SIZE {
+                cells[x * BOARD_SIZE + y] = board.get(x, y);
+            }
+        }
+        let built = Stack4::from_cells(&cells, Some(Player::Yellow)).unwrap();
+        assert_eq!(built.uid(), board.uid());
+        assert_eq!(built.key(), board.key());
+        assert_eq!(built.nb_moves, 5);
+
+        // a piece in the middle of the board.
+        cells[3 * BOARD_SIZE + 4] = 2;
+        assert_eq!(
+            Stack4::from_cells(&cells, None).unwrap_err(),
+            PositionError::FloatingPiece { x: 3, y: 4 }
+        );
+        cells[3 * BOARD_SIZE + 4] = 3;
+        assert_eq!(
+            Stack4::from_cells(&cells, None).unwrap_err(),
+            PositionError::InvalidCell {
+                x: 3,
+                y: 4,
+                value: 3
+            }
+        );
+        assert_eq!(
+            Stack4::from_cells(&cells[1..], None).unwrap_err(),
+            PositionError::WrongSize {
+                expected: 64,
+                found: 63
+            }
+        );
+    }
 }
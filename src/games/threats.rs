@@ -0,0 +1,145 @@
+use crate::games::Player;
+
+// The empty squares where a piece would complete a line that counts under the rules, for both
+// players. Squares are bitboards in the layout of the game they came from, see
+// ConnectN::threats and StackN::threats.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Threats {
+    // Indexed by Player as usize - 1.
+    squares: [u128; 2],
+    immediate: [u128; 2],
+    odd: [u128; 2],
+    even: [u128; 2],
+    stacked: [u128; 2],
+}
+
+impl Threats {
+    // 'playable' are the squares that can be played now and 'odd_rows' the squares on the
+    // first, third, fifth.. rows from the bottom. 'below' maps squares to the squares right
+    // below them. Games without gravity give 0 for odd_rows and nothing below.
+    pub(crate) fn new(
+        squares: [u128; 2],
+        playable: u128,
+        odd_rows: u128,
+        below: impl Fn(u128) -> u128,
+    ) -> Self {
+        let mut threats = Threats::default();
+        for (i, &tiles) in squares.iter().enumerate() {
+            threats.squares[i] = tiles;
+            threats.immediate[i] = tiles & playable;
+            if odd_rows != 0 {
+                threats.odd[i] = tiles & odd_rows;
+                threats.even[i] = tiles & !odd_rows;
+            }
+            threats.stacked[i] = threats.immediate[i] & below(tiles);
+        }
+        threats
+    }
+
+    // Every square that would give 'player' a line.
+    pub fn squares(&self, player: Player) -> u128 {
+        self.squares[player as usize - 1]
+    }
+
+    // The threats of 'player' that can be played right now. If it is the opponent's move they
+    // have to block these.
+    pub fn immediate(&self, player: Player) -> u128 {
+        self.immediate[player as usize - 1]
+    }
+
+    // Connect4 only: threats on the first, third and fifth rows from the bottom. When the
+    // board fills up the first player can usually only play out odd threats and the second
+    // player even ones.
+    pub fn odd(&self, player: Player) -> u128 {
+        self.odd[player as usize - 1]
+    }
+
+    // Connect4 only: threats on the second, fourth and sixth rows from the bottom.
+    pub fn even(&self, player: Player) -> u128 {
+        self.even[player as usize - 1]
+    }
+
+    // Immediate threats with another threat for the same player right above them, blocking
+    // the first one lets the player play the second.
+    pub fn stacked(&self, player: Player) -> u128 {
+        self.stacked[player as usize - 1]
+    }
+
+    // True if the opponent can't block every threat of 'player' with one move, either because
+    // there are two immediate threats or because one of them is stacked.
+    pub fn double(&self, player: Player) -> bool {
+        self.immediate(player).count_ones() >= 2 || self.stacked(player) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::games::connect4::Connect4;
+    use crate::games::stack4::Stack4;
+    use crate::games::{Game, Player};
+
+    // Bit of (x, y) in the Connect4 bitboards.
+    fn c4(x: usize, y: usize) -> u128 {
+        1 << (x * 7 + y)
+    }
+
+    #[test]
+    fn connect4() {
+        let mut board = Connect4::new();
+        for action in [0, 0, 1, 1, 2] {
+            board.play_action(action);
+        }
+        let threats = board.threats();
+        assert_eq!(threats.squares(Player::Red), c4(3, 0));
+        assert_eq!(threats.immediate(Player::Red), c4(3, 0));
+        assert_eq!(threats.odd(Player::Red), c4(3, 0));
+        assert_eq!(threats.even(Player::Red), 0);
+        assert_eq!(threats.squares(Player::Yellow), 0);
+        assert!(!threats.double(Player::Red));
+
+        let mut board = Connect4::new();
+        for action in [1, 1, 2, 2, 3] {
+            board.play_action(action);
+        }
+        let threats = board.threats();
+        assert_eq!(threats.immediate(Player::Red), c4(0, 0) | c4(4, 0));
+        assert!(threats.double(Player::Red));
+        assert_eq!(threats.stacked(Player::Red), 0);
+    }
+
+    #[test]
+    fn connect4_stacked() {
+        // red has three in a row on the two bottom rows.
+        let mut cells = vec![0; 42];
+        for x in 0..3 {
+            cells[x * 6] = 1;
+            cells[x * 6 + 1] = 1;
+        }
+        for (x, y) in [(0, 2), (1, 2), (5, 0), (5, 1), (6, 0), (6, 1)] {
+            cells[x * 6 + y] = 2;
+        }
+        let board = Connect4::from_cells(&cells, None).unwrap();
+        let threats = board.threats();
+        assert_eq!(threats.squares(Player::Red), c4(3, 0) | c4(3, 1));
+        assert_eq!(threats.immediate(Player::Red), c4(3, 0));
+        assert_eq!(threats.stacked(Player::Red), c4(3, 0));
+        assert_eq!(threats.odd(Player::Red), c4(3, 0));
+        assert_eq!(threats.even(Player::Red), c4(3, 1));
+        assert!(threats.double(Player::Red));
+        assert!(!threats.double(Player::Yellow));
+    }
+
+    #[test]
+    fn stack4() {
+        let mut board = Stack4::new();
+        for action in [(0, 0), (7, 7), (0, 1), (7, 6), (0, 2)] {
+            board.play_action(action);
+        }
+        let threats = board.threats();
+        assert_eq!(threats.squares(Player::Red), 1 << 24);
+        assert_eq!(threats.immediate(Player::Red), 1 << 24);
+        assert_eq!(threats.odd(Player::Red), 0);
+        assert_eq!(threats.squares(Player::Yellow), 0);
+        assert!(!threats.double(Player::Red));
+    }
+}
@@ -2,6 +2,7 @@ pub mod agents;
 pub mod evaluators;
 pub mod games;
 pub mod matchmaker;
+pub mod perft;
 pub mod policies;
 pub mod qlearning;
 pub mod search;
@@ -132,7 +132,7 @@ where
 
 pub fn user_vs_user<G: PlayableGame>() {
     let mut board = G::new();
-    let mut last_action: Option<G::Action> = None;
+    let mut last_undo: Option<G::Undo> = None;
     let mut actions = Vec::new();
     loop {
         println!("{:?}", board);
@@ -140,12 +140,11 @@ pub fn user_vs_user<G: PlayableGame>() {
         println!("{:?}", board.game_state());
         let (action, reverse) = board.get_action_from_user();
         if reverse {
-            if let Some(last_action) = last_action {
-                board.reverse_last_action(last_action);
+            if let Some(last_undo) = last_undo {
+                board.reverse_last_action(last_undo);
             }
         } else {
-            last_action = Some(action);
-            board.play_action(action);
+            last_undo = Some(board.play_action(action));
             actions.push(action);
             match board.game_state() {
                 GameState::Draw => {
@@ -171,31 +170,32 @@ where
     let mut board = G::new();
     let p = board.cur_player();
 
-    let mut actions = Vec::new();
+    let mut undos = Vec::new();
 
     loop {
         println!("{:?}", board);
         let (action, reverse) = board.get_action_from_user();
         if reverse {
-            board.reverse_last_action(actions[actions.len() - 1]);
-            board.reverse_last_action(actions[actions.len() - 2]);
-            actions.remove(actions.len() - 1);
-            actions.remove(actions.len() - 1);
+            board.reverse_last_action(undos[undos.len() - 1]);
+            board.reverse_last_action(undos[undos.len() - 2]);
+            undos.remove(undos.len() - 1);
+            undos.remove(undos.len() - 1);
             continue;
         } else {
-            actions.push(action);
-            board.play_action(action);
+            undos.push(board.play_action(action));
             println!("{:?}", board);
             if board.game_state() != GameState::InProgress {
                 break;
             }
         }
-        let action = opponent.get_action(&board, !p);
-        unsafe {
-            crate::search::LEAF_COUNT = 0;
-        }
-        actions.push(action);
-        board.play_action(action);
+        let action = match opponent.search(&board, !p) {
+            Some(result) => {
+                println!("{:#}", result);
+                result.best
+            }
+            None => opponent.get_action(&board, !p),
+        };
+        undos.push(board.play_action(action));
         if board.game_state() != GameState::InProgress {
             break;
         }
@@ -0,0 +1,84 @@
+use crate::games::{Game, GameState, MoveList};
+
+// Number of positions reached after 'depth' moves from the starting position. Positions where
+// the game ended earlier aren't counted.
+pub const CONNECT4_PERFT: [u64; 10] = [
+    1, 7, 49, 343, 2401, 16807, 117649, 823536, 5673234, 39394572,
+];
+pub const STACK4_PERFT: [u64; 6] = [1, 28, 780, 21616, 595888, 16339016];
+
+// Counts the positions that can be reached from 'board' in exactly 'depth' moves. Comparing
+// the counts with known values checks play_action, reverse_last_action and move generation.
+pub fn perft<G: Game>(board: &mut G, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    if board.game_state() != GameState::InProgress {
+        return 0;
+    }
+    let mut moves = MoveList::new();
+    board.generate_moves(&mut moves);
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+    let mut count = 0;
+    for &action in moves.iter() {
+        let undo = board.play_action(action);
+        count += perft(board, depth - 1);
+        board.reverse_last_action(undo);
+    }
+    count
+}
+
+// perft split by the first move, useful to find which move a wrong count comes from.
+pub fn divide<G: Game>(board: &mut G, depth: u32) -> Vec<(G::Action, u64)> {
+    if depth == 0 || board.game_state() != GameState::InProgress {
+        return Vec::new();
+    }
+    let mut moves = MoveList::new();
+    board.generate_moves(&mut moves);
+    moves
+        .iter()
+        .map(|&action| {
+            let undo = board.play_action(action);
+            let count = perft(board, depth - 1);
+            board.reverse_last_action(undo);
+            (action, count)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::games::connect4::Connect4;
+    use crate::games::stack4::Stack4;
+
+    #[test]
+    fn connect4() {
+        let mut board = Connect4::new();
+        for (depth, &count) in CONNECT4_PERFT.iter().enumerate().take(7) {
+            assert_eq!(perft(&mut board, depth as u32), count);
+        }
+        assert_eq!(board.uid(), Connect4::new().uid());
+    }
+
+    #[test]
+    fn stack4() {
+        let mut board = Stack4::new();
+        for (depth, &count) in STACK4_PERFT.iter().enumerate().take(4) {
+            assert_eq!(perft(&mut board, depth as u32), count);
+        }
+        assert_eq!(board.uid(), Stack4::new().uid());
+    }
+
+    #[test]
+    fn divide_sums_to_perft() {
+        let mut board = Connect4::new();
+        let counts = divide(&mut board, 5);
+        assert_eq!(counts.len(), 7);
+        assert!(counts.iter().all(|&(_, count)| count == 2401));
+        let total: u64 = counts.iter().map(|&(_, count)| count).sum();
+        assert_eq!(total, CONNECT4_PERFT[5]);
+    }
+}
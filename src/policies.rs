@@ -4,6 +4,16 @@ use serde::{Deserialize, Serialize};
 pub trait Policy {
     // returns index of chosen value.
     fn choose(&self, action_values: &Vec<f64>) -> usize;
+
+    // 'action_values' has one value per action index, see Game::action_to_index. Chooses
+    // among the indices set in 'mask' and returns the chosen index.
+    fn choose_masked(&self, action_values: &[f64], mask: u128) -> usize {
+        let indices: Vec<usize> = (0..action_values.len())
+            .filter(|&i| mask >> i & 1 == 1)
+            .collect();
+        let values = indices.iter().map(|&i| action_values[i]).collect();
+        indices[self.choose(&values)]
+    }
 }
 
 #[derive(Serialize, Deserialize)]
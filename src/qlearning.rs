@@ -2,12 +2,14 @@ use crate::agents::{Agent, BatchMinimaxAgent, MinimaxPolicyAgent};
 use crate::evaluators::Evaluator;
 use crate::games::{Game, GameState, Player};
 use crate::policies::Policy;
-use crate::search::abnegamax;
+use crate::search::{abnegamax, is_loss, is_win};
 use serde::{Deserialize, Serialize};
 
 // returns the board after every move. which means that it excludes starting position but includes end position.
 // p1 always starts.
 // p1 is Player::Red, p2 is Player::Yellow.
+// The episode ends as soon as the game is decided, which includes games that are drawn
+// because no player has a live line left, see Game::live_lines.
 pub fn episode<G: Game>(p1: &dyn Agent<G>, p2: &dyn Agent<G>) -> Vec<(G, bool)> {
     let mut boards = Vec::new();
     let mut board = G::new();
@@ -115,7 +117,7 @@ where
                     }
                 }
             } else {
-                let v = abnegamax(
+                let (v, _) = abnegamax(
                     *next_state,
                     self.depth,
                     self.batch_depth,
@@ -125,9 +127,9 @@ where
                 );
 
                 // The reward is baked into the target action value.
-                if v == 1. / 0. {
+                if is_win(v) {
                     1.0
-                } else if v == -1. / 0. {
+                } else if is_loss(v) {
                     -1.0
                 } else {
                     v
@@ -1,104 +1,568 @@
 use crate::evaluators::Evaluator;
-use crate::games::{Game, GameState, Player};
+use crate::games::{Game, GameState, MoveList, Player};
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-pub static mut LEAF_COUNT: u32 = 0;
+// Deepest iteration of iterative deepening, for searches that are only limited by time.
+pub const MAX_DEPTH: u32 = 128;
 
-// these two numbers must be coprime.
-const TABLE_SIZE: usize = 104723;
-const MULTIPLIER: usize = 48619;
+// How many nodes are searched between checks of the clock and the cancel flag.
+const CHECK_INTERVAL: u64 = 1024;
 
-pub struct TranspositionTable<T> {
-    pub table: Vec<Option<(u128, T)>>,
+// Number of buckets, must be a power of two.
+const TABLE_SIZE: usize = 1 << 16;
+
+// Evaluators give +-infinity for decided games, which the search turns into finite scores so
+// that they can be told apart: a win in n moves scores WIN - n and a loss in n moves
+// -(WIN - n), so faster wins and slower losses score higher. Scores are relative to the
+// position they belong to, which lets the transposition table store them as they are.
+// Heuristic values must stay below WIN - MAX_MOVES_TO_END.
+pub const WIN: f64 = 1e9;
+const MAX_MOVES_TO_END: f64 = 1e6;
+
+pub fn win_in(moves: u32) -> f64 {
+    WIN - moves as f64
+}
+
+pub fn loss_in(moves: u32) -> f64 {
+    -win_in(moves)
+}
+
+pub fn is_win(score: f64) -> bool {
+    score >= WIN - MAX_MOVES_TO_END
+}
+
+pub fn is_loss(score: f64) -> bool {
+    score <= -(WIN - MAX_MOVES_TO_END)
+}
+
+// The number of moves until the game ends if 'score' is a win or loss.
+pub fn moves_to_end(score: f64) -> Option<u32> {
+    if is_win(score) || is_loss(score) {
+        Some((WIN - score.abs()) as u32)
+    } else {
+        None
+    }
+}
+
+// "win in N", "loss in N" or the heuristic value.
+pub fn format_score(score: f64) -> String {
+    match moves_to_end(score) {
+        Some(n) if is_win(score) => format!("win in {}", n),
+        Some(n) => format!("loss in {}", n),
+        None => format!("{:+.3}", score),
+    }
+}
+
+// The score of a position reached by a move, for the player who made the move: a win or
+// loss is one move further away than from the position itself.
+pub fn parent_score(score: f64) -> f64 {
+    let score = -score;
+    if is_win(score) {
+        score - 1.0
+    } else if is_loss(score) {
+        score + 1.0
+    } else {
+        score
+    }
+}
+
+// The inverse of parent_score, turns a bound of the parent into one of the child.
+fn child_score(score: f64) -> f64 {
+    if is_win(score) {
+        -(score + 1.0)
+    } else if is_loss(score) {
+        -(score - 1.0)
+    } else {
+        -score
+    }
+}
+
+// The value of a position where the search stops.
+fn leaf_score(value: f64) -> f64 {
+    if value == 1. / 0. {
+        WIN
+    } else if value == -1. / 0. {
+        -WIN
+    } else {
+        value
+    }
+}
+
+// How the value of an entry relates to the true value of the position.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Bound {
+    Exact,
+    // The search failed high so the true value is at least the stored one.
+    Lower,
+    // The search failed low so the true value is at most the stored one.
+    Upper,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Entry<A, U> {
+    pub uid: U,
+    // How many moves deep the position was searched.
+    pub depth: u32,
+    pub value: f64,
+    pub bound: Bound,
+    // The move that gave the value, tried first the next time the position is searched.
+    pub best: Option<A>,
+    // The search the entry was stored in, see TranspositionTable::new_search.
+    pub age: u8,
+}
+
+// Entries are found with the Zobrist key of a position and store its uid to tell positions
+// that share a bucket apart. Every bucket has two entries, the first keeps the deepest search
+// of the current age and the second takes everything else.
+pub struct TranspositionTable<A, U> {
+    buckets: Vec<[Option<Entry<A, U>>; 2]>,
+    age: u8,
 }
 
-pub fn hash(board: u128) -> usize {
-    (board * MULTIPLIER as u128 % TABLE_SIZE as u128) as usize
+// The low bits of a Zobrist key are as random as the rest so they can be used directly.
+fn index(key: u64) -> usize {
+    key as usize & (TABLE_SIZE - 1)
 }
 
-impl<T: Copy> TranspositionTable<T> {
-    pub fn new() -> TranspositionTable<T> {
+impl<A: Copy, U: Copy + PartialEq> TranspositionTable<A, U> {
+    pub fn new() -> TranspositionTable<A, U> {
         TranspositionTable {
-            table: vec![None; TABLE_SIZE],
+            buckets: vec![[None; 2]; TABLE_SIZE],
+            age: 0,
         }
     }
 
-    pub fn get(&self, board: u128) -> Option<T> {
-        if let Some((b, val)) = &self.table[hash(board)] {
-            if board == *b {
-                Some(*val)
-            } else {
-                None
-            }
+    // Makes the entries stored so far old, so that the ones of the next search replace them
+    // even if they are shallower. They are still found until they are replaced.
+    pub fn new_search(&mut self) {
+        self.age = self.age.wrapping_add(1);
+    }
+
+    pub fn get(&self, key: u64, uid: U) -> Option<Entry<A, U>> {
+        self.buckets[index(key)]
+            .iter()
+            .flatten()
+            .find(|entry| entry.uid == uid)
+            .copied()
+    }
+
+    pub fn store(
+        &mut self,
+        key: u64,
+        uid: U,
+        depth: u32,
+        value: f64,
+        bound: Bound,
+        best: Option<A>,
+    ) {
+        let age = self.age;
+        let entry = Entry {
+            uid,
+            depth,
+            value,
+            bound,
+            best,
+            age,
+        };
+        let bucket = &mut self.buckets[index(key)];
+        let replace_deepest = match bucket[0] {
+            Some(deepest) => deepest.uid == uid || deepest.age != age || deepest.depth <= depth,
+            None => true,
+        };
+        if replace_deepest {
+            bucket[0] = Some(entry);
         } else {
-            None
+            bucket[1] = Some(entry);
+        }
+    }
+}
+
+// When iterative deepening has to stop, see iterative_deepening. The default has no limits.
+#[derive(Clone, Default)]
+pub struct SearchLimits {
+    pub deadline: Option<Instant>,
+    // Nodes searched in total, over all iterations.
+    pub max_nodes: Option<u64>,
+    // Set from another thread to stop the search.
+    pub cancel: Option<Arc<AtomicBool>>,
+}
+
+impl SearchLimits {
+    // Stops 'budget' from now.
+    pub fn time(budget: Duration) -> Self {
+        SearchLimits {
+            deadline: Some(Instant::now() + budget),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_max_nodes(mut self, max_nodes: u64) -> Self {
+        self.max_nodes = Some(max_nodes);
+        self
+    }
+
+    pub fn with_cancel(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+}
+
+// What a search did, to tune move ordering and batch depth.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct SearchStats {
+    // Positions visited, leaves included.
+    pub nodes: u64,
+    // Positions where the search stopped because the game was over or the depth reached.
+    pub leaves: u64,
+    // Calls to Evaluator::value.
+    pub evaluations: u64,
+    // Calls to Evaluator::values and the number of positions given to them.
+    pub batches: u64,
+    pub batched_positions: u64,
+    pub tt_probes: u64,
+    pub tt_hits: u64,
+    // Hits that ended the search of a position without searching its moves.
+    pub tt_cutoffs: u64,
+    // beta_cutoffs[i] is how many times the i:th move searched in a position was good enough
+    // to skip the rest, so good move ordering has most of them at 0.
+    pub beta_cutoffs: Vec<u64>,
+    pub elapsed: Duration,
+}
+
+impl SearchStats {
+    fn beta_cutoff(&mut self, move_index: usize) {
+        if self.beta_cutoffs.len() <= move_index {
+            self.beta_cutoffs.resize(move_index + 1, 0);
+        }
+        self.beta_cutoffs[move_index] += 1;
+    }
+
+    // Adds the counts of 'other', for results made from several searches.
+    pub fn merge(&mut self, other: &SearchStats) {
+        self.nodes += other.nodes;
+        self.leaves += other.leaves;
+        self.evaluations += other.evaluations;
+        self.batches += other.batches;
+        self.batched_positions += other.batched_positions;
+        self.tt_probes += other.tt_probes;
+        self.tt_hits += other.tt_hits;
+        self.tt_cutoffs += other.tt_cutoffs;
+        if self.beta_cutoffs.len() < other.beta_cutoffs.len() {
+            self.beta_cutoffs.resize(other.beta_cutoffs.len(), 0);
+        }
+        for (count, other_count) in self.beta_cutoffs.iter_mut().zip(&other.beta_cutoffs) {
+            *count += other_count;
+        }
+        self.elapsed += other.elapsed;
+    }
+}
+
+impl fmt::Display for SearchStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} nodes, {} leaves, {} evaluations, {} batches of {} positions, \
+             tt {} probes {} hits {} cutoffs, beta cutoffs by move {:?} in {:.2?}",
+            self.nodes,
+            self.leaves,
+            self.evaluations,
+            self.batches,
+            self.batched_positions,
+            self.tt_probes,
+            self.tt_hits,
+            self.tt_cutoffs,
+            self.beta_cutoffs,
+            self.elapsed
+        )
+    }
+}
+
+// Keeps the statistics of a search and tells it when one of the limits is reached.
+struct Budget<'a> {
+    limits: &'a SearchLimits,
+    stats: SearchStats,
+    stopped: bool,
+    // Node count at which the clock and cancel flag are checked next. Batches count many
+    // nodes at once so the count can skip over any given number.
+    next_check: u64,
+}
+
+impl<'a> Budget<'a> {
+    fn new(limits: &'a SearchLimits) -> Self {
+        Budget {
+            limits,
+            stats: SearchStats::default(),
+            stopped: false,
+            next_check: CHECK_INTERVAL,
+        }
+    }
+
+    // Counts one more node and returns true if the search has to stop.
+    fn tick(&mut self) -> bool {
+        self.stats.nodes += 1;
+        let nodes = self.stats.nodes;
+        if matches!(self.limits.max_nodes, Some(max) if nodes > max) {
+            self.stopped = true;
+        } else if nodes >= self.next_check {
+            self.next_check = nodes + CHECK_INTERVAL;
+            let late = matches!(self.limits.deadline, Some(d) if Instant::now() >= d);
+            let cancelled = matches!(&self.limits.cancel, Some(c) if c.load(Ordering::Relaxed));
+            self.stopped = late || cancelled;
+        }
+        self.stopped
+    }
+}
+
+// Picks one of the actions with the highest value at random.
+pub fn choose_best<A: Copy>(avs: &[(A, f64)]) -> A {
+    let mx = avs.iter().map(|(_, v)| *v).fold(-1.0 / 0.0, f64::max);
+    let best_avs = avs.iter().filter(|(_, v)| *v == mx).collect::<Vec<_>>();
+    best_avs[fastrand::usize(0..best_avs.len())].0
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RootScore<A> {
+    pub action: A,
+    pub value: f64,
+    pub bound: Bound,
+}
+
+// What a search found out about a position and why it picked its move.
+#[derive(Clone, PartialEq, Debug)]
+pub struct SearchResult<A> {
+    pub best: A,
+    // Value of 'best' for the player to move.
+    pub value: f64,
+    // The moves both players are expected to play, starting with 'best'.
+    pub pv: Vec<A>,
+    // Every root move searched, best first.
+    pub root_scores: Vec<RootScore<A>>,
+    // Depth of the deepest search that finished.
+    pub depth: u32,
+    pub stats: SearchStats,
+}
+
+impl<A> SearchResult<A> {
+    pub fn score(&self) -> String {
+        format_score(self.value)
+    }
+}
+
+// One line with the move, score, depth, time and pv. The alternate form, {:#}, also lists the
+// value of every root move and the statistics.
+impl<A: fmt::Debug> fmt::Display for SearchResult<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} {} depth {} in {:.2?} pv",
+            self.best,
+            self.score(),
+            self.depth,
+            self.stats.elapsed
+        )?;
+        for action in &self.pv {
+            write!(f, " {:?}", action)?;
+        }
+        if f.alternate() {
+            for root in &self.root_scores {
+                let bound = match root.bound {
+                    Bound::Exact => "",
+                    Bound::Lower => ">=",
+                    Bound::Upper => "<=",
+                };
+                write!(
+                    f,
+                    "\n  {:?} {}{}",
+                    root.action,
+                    bound,
+                    format_score(root.value)
+                )?;
+            }
+            write!(f, "\n{}", self.stats)?;
+        }
+        Ok(())
+    }
+}
+
+// Follows the best moves stored in 'tt' from 'board' after 'best', for at most 'depth' moves.
+fn principal_variation<T: Game>(
+    board: &T,
+    best: T::Action,
+    tt: &TranspositionTable<T::Action, T::Uid>,
+    depth: u32,
+) -> Vec<T::Action> {
+    let mut board = *board;
+    let mut pv = vec![best];
+    board.play_action(best);
+    while board.game_state() == GameState::InProgress && (pv.len() as u32) < depth {
+        match tt
+            .get(board.key(), board.uid())
+            .and_then(|entry| entry.best)
+        {
+            Some(action) => {
+                board.play_action(action);
+                pv.push(action);
+            }
+            None => break,
         }
     }
+    pv
+}
 
-    pub fn set(&mut self, board: u128, value: T) {
-        self.table[hash(board)] = Some((board, value));
+// Searches 'actions' for 'player' with iterative deepening, at most 'max_depth' moves ahead,
+// and returns the deepest iteration that finished within 'limits'. The first iteration always
+// finishes so that there is a value for every action. Every iteration searches the actions in
+// the order of the values of the previous one, and the transposition table kept between
+// iterations gives the best moves further down. Root moves get a full window so their values
+// are exact and ties are broken at random. A table given in 'tt' is kept by the caller between
+// searches with the same evaluator, otherwise the search starts with a new one.
+pub fn iterative_deepening<T, E>(
+    board: &T,
+    actions: &[T::Action],
+    max_depth: u32,
+    batch_depth: u32,
+    evaluator: &E,
+    player: Player,
+    limits: &SearchLimits,
+    tt: Option<&mut TranspositionTable<T::Action, T::Uid>>,
+) -> SearchResult<T::Action>
+where
+    T: Game,
+    E: Evaluator<T>,
+{
+    let start = Instant::now();
+    let mut new_table;
+    let tt = match tt {
+        Some(t) => t,
+        None => {
+            new_table = TranspositionTable::new();
+            &mut new_table
+        }
+    };
+    tt.new_search();
+    let mut board = *board;
+    let mut avs: Vec<_> = actions.iter().map(|&a| (a, 0.0)).collect();
+    let mut completed = 0;
+    let no_limits = SearchLimits::default();
+    let mut budget = Budget::new(&no_limits);
+    'deepening: for depth in 1..=max_depth {
+        let mut values = Vec::with_capacity(avs.len());
+        for &(action, _) in &avs {
+            let undo = board.play_action(action);
+            let v = parent_score(_abnegamax(
+                &mut board,
+                -1. / 0.,
+                1. / 0.,
+                depth - 1,
+                batch_depth,
+                evaluator,
+                !player,
+                tt,
+                &mut budget,
+            ));
+            board.reverse_last_action(undo);
+            if budget.stopped {
+                break 'deepening;
+            }
+            values.push((action, v));
+        }
+        avs = values;
+        avs.sort_by(|(_, v1), (_, v2)| v2.total_cmp(v1));
+        completed = depth;
+        // once every action is a known win or loss searching deeper can't change anything, and
+        // a win found at this depth is at least as fast as any found deeper.
+        if avs.iter().all(|(_, v)| moves_to_end(*v).is_some())
+            || avs.iter().any(|(_, v)| is_win(*v))
+        {
+            break;
+        }
+        budget.limits = limits;
+    }
+    let best = choose_best(&avs);
+    let pv = principal_variation(&board, best, tt, completed);
+    let mut stats = budget.stats;
+    stats.elapsed = start.elapsed();
+    SearchResult {
+        best,
+        value: avs.iter().find(|(a, _)| *a == best).unwrap().1,
+        pv,
+        root_scores: avs
+            .iter()
+            .map(|&(action, value)| RootScore {
+                action,
+                value,
+                bound: Bound::Exact,
+            })
+            .collect(),
+        depth: completed,
+        stats,
     }
 }
 
+// Searches every legal action for 'player', see iterative_deepening.
+pub fn search_position<T, E>(
+    board: &T,
+    max_depth: u32,
+    batch_depth: u32,
+    evaluator: &E,
+    player: Player,
+    limits: &SearchLimits,
+) -> SearchResult<T::Action>
+where
+    T: Game,
+    E: Evaluator<T>,
+{
+    let actions: Vec<_> = board.legal_actions().collect();
+    iterative_deepening(
+        board,
+        &actions,
+        max_depth,
+        batch_depth,
+        evaluator,
+        player,
+        limits,
+        None,
+    )
+}
+
 pub fn abnegamax_best_action<T, E>(
     board: &T,
     depth: u32,
     evaluator: &E,
     player: Player,
-) -> T::Action
+) -> SearchResult<T::Action>
 where
     T: Game,
     E: Evaluator<T>,
     T::Action: Copy,
 {
-    let mut tt = TranspositionTable::new();
-    let mut _board = board.clone();
-    let mut avs = Vec::new();
-    for action in _board.legal_actions() {
-        _board.play_action(action);
-        avs.push((
-            action,
-            -abnegamax(&_board, depth - 1, 0, evaluator, !player, Some(&mut tt)),
-        ));
-        _board.reverse_last_action(action);
-    }
-    let mx = avs.iter().map(|(_, v)| *v).fold(-1.0 / 0.0, f64::max);
-    let best_avs = avs
-        .iter()
-        .filter(|(_, v)| *v == mx)
-        .collect::<Vec<&(T::Action, f64)>>();
-    best_avs[fastrand::usize(0..best_avs.len())].0
+    search_position(board, depth, 0, evaluator, player, &SearchLimits::default())
 }
 
+// Like abnegamax_best_action but everything below the root moves is evaluated in batches.
 pub fn batch_negamax_best_action<T, E>(
     board: &T,
     depth: u32,
     evaluator: &E,
     player: Player,
-) -> T::Action
+) -> SearchResult<T::Action>
 where
     T: Game,
     E: Evaluator<T>,
     T::Action: Copy,
 {
-    let mut _board = board.clone();
-    let mut avs = Vec::new();
-    for action in _board.legal_actions() {
-        _board.play_action(action);
-        avs.push((
-            action,
-            -batch_negamax(&_board, depth - 1, evaluator, !player),
-        ));
-        _board.reverse_last_action(action);
-    }
-    //println!("{:?}", avs);
-    let mx = avs.iter().map(|(_, v)| *v).fold(-1.0 / 0.0, f64::max);
-    let best_avs = avs
-        .iter()
-        .filter(|(_, v)| *v == mx)
-        .collect::<Vec<&(T::Action, f64)>>();
-    //println!("{:?}", avs);
-    best_avs[fastrand::usize(0..best_avs.len())].0
+    search_position(
+        board,
+        depth,
+        depth,
+        evaluator,
+        player,
+        &SearchLimits::default(),
+    )
 }
 
 pub fn batch_abnegamax_best_action<T, E>(
@@ -107,54 +571,61 @@ pub fn batch_abnegamax_best_action<T, E>(
     batch_depth: u32,
     evaluator: &E,
     player: Player,
-) -> T::Action
+) -> SearchResult<T::Action>
 where
     T: Game,
     E: Evaluator<T>,
     T::Action: Copy,
 {
-    let mut tt = TranspositionTable::new();
-    let mut _board = board.clone();
-    let mut avs = Vec::new();
-    for action in _board.legal_actions() {
-        _board.play_action(action);
-        avs.push((
-            action,
-            -abnegamax(
-                &_board,
-                depth - 1,
-                batch_depth,
-                evaluator,
-                !player,
-                Some(&mut tt),
-            ),
-        ));
-        _board.reverse_last_action(action);
-    }
-    //println!("{:?}", avs);
-    let mx = avs.iter().map(|(_, v)| *v).fold(-1.0 / 0.0, f64::max);
-    let best_avs = avs
-        .iter()
-        .filter(|(_, v)| *v == mx)
-        .collect::<Vec<&(T::Action, f64)>>();
-    //println!("{:?}", avs);
-    best_avs[fastrand::usize(0..best_avs.len())].0
+    search_position(
+        board,
+        depth,
+        batch_depth,
+        evaluator,
+        player,
+        &SearchLimits::default(),
+    )
+}
+
+pub fn negamax<T, E>(board: &mut T, depth: u32, evaluator: &E, player: Player) -> (f64, SearchStats)
+where
+    T: Game,
+    E: Evaluator<T>,
+    T::Action: Copy,
+{
+    let start = Instant::now();
+    let mut stats = SearchStats::default();
+    let value = _negamax(board, depth, evaluator, player, &mut stats);
+    stats.elapsed = start.elapsed();
+    (value, stats)
 }
 
-pub fn negamax<T, E>(board: &mut T, depth: u32, evaluator: &E, player: Player) -> f64
+fn _negamax<T, E>(
+    board: &mut T,
+    depth: u32,
+    evaluator: &E,
+    player: Player,
+    stats: &mut SearchStats,
+) -> f64
 where
     T: Game,
     E: Evaluator<T>,
     T::Action: Copy,
 {
+    stats.nodes += 1;
     if board.game_state() != GameState::InProgress || depth == 0 {
-        return evaluator.value(board, player);
+        stats.leaves += 1;
+        stats.evaluations += 1;
+        return leaf_score(evaluator.value(board, player));
     }
+    // The value doesn't depend on the order so the moves aren't ordered.
+    let mut moves = MoveList::new();
+    board.generate_moves(&mut moves);
     let mut val: f64 = -1. / 0.;
-    for action in board.legal_actions() {
-        board.play_action(action);
-        let v = -negamax(board, depth - 1, evaluator, !player);
-        board.reverse_last_action(action);
+    for &action in moves.iter() {
+        let undo = board.play_action(action);
+        let v = parent_score(_negamax(board, depth - 1, evaluator, !player, stats));
+        board.reverse_last_action(undo);
         val = val.max(v);
     }
     val
@@ -166,15 +637,18 @@ pub fn abnegamax<T, E>(
     batch_depth: u32,
     evaluator: &E,
     player: Player,
-    tt: Option<&mut TranspositionTable<f64>>,
-) -> f64
+    tt: Option<&mut TranspositionTable<T::Action, T::Uid>>,
+) -> (f64, SearchStats)
 where
     T: Game,
     E: Evaluator<T>,
     T::Action: Copy,
 {
+    let start = Instant::now();
     let mut _board = board.clone();
-    if let Some(t) = tt {
+    let limits = SearchLimits::default();
+    let mut budget = Budget::new(&limits);
+    let value = if let Some(t) = tt {
         _abnegamax(
             &mut _board,
             -1. / 0.,
@@ -184,6 +658,7 @@ where
             evaluator,
             player,
             t,
+            &mut budget,
         )
     } else {
         let mut t = TranspositionTable::new();
@@ -196,8 +671,11 @@ where
             evaluator,
             player,
             &mut t,
+            &mut budget,
         )
-    }
+    };
+    budget.stats.elapsed = start.elapsed();
+    (value, budget.stats)
 }
 
 fn _abnegamax<T, E>(
@@ -208,56 +686,129 @@ fn _abnegamax<T, E>(
     batch_depth: u32,
     evaluator: &E,
     player: Player,
-    tt: &mut TranspositionTable<f64>,
+    tt: &mut TranspositionTable<T::Action, T::Uid>,
+    budget: &mut Budget,
 ) -> f64
 where
     T: Game,
     E: Evaluator<T>,
     T::Action: Copy,
 {
-    if board.game_state() != GameState::InProgress || depth == 0 {
-        unsafe { LEAF_COUNT += 1 };
-        return evaluator.value(board, player);
+    // the value of a stopped search is never used.
+    if budget.tick() {
+        return 0.0;
     }
-    let mut max = 1. / 0.;
-    if let Some(val) = tt.get(board.uid()) {
-        max = val;
+    if board.game_state() != GameState::InProgress || depth == 0 {
+        budget.stats.leaves += 1;
+        budget.stats.evaluations += 1;
+        return leaf_score(evaluator.value(board, player));
     }
-    if beta > max {
-        beta = max;
-        if alpha >= beta {
-            return beta;
+    // In games like PopOut the same position can be reached after a different number of
+    // moves, so an entry only ends the search if it was searched at least as deep.
+    let mut hash_move = None;
+    budget.stats.tt_probes += 1;
+    if let Some(entry) = tt.get(board.key(), board.uid()) {
+        budget.stats.tt_hits += 1;
+        hash_move = entry.best;
+        if entry.depth >= depth {
+            match entry.bound {
+                Bound::Exact => {
+                    budget.stats.tt_cutoffs += 1;
+                    return entry.value;
+                }
+                Bound::Lower => alpha = alpha.max(entry.value),
+                Bound::Upper => beta = beta.min(entry.value),
+            }
+            if alpha >= beta {
+                budget.stats.tt_cutoffs += 1;
+                return entry.value;
+            }
         }
     }
+    let mut moves = MoveList::new();
+    board.generate_moves(&mut moves);
+    board.order_moves(&mut moves);
+    if let Some(i) = moves.iter().position(|&a| Some(a) == hash_move) {
+        moves[..=i].rotate_right(1);
+    }
+    // The window the moves are searched with, which the bounds of the table may have narrowed.
+    // A value at or below it is only an upper bound even if it is above the alpha given.
+    let searched_alpha = alpha;
     let mut val: f64 = -1. / 0.;
-    for action in board.legal_actions() {
-        board.play_action(action);
+    let mut best = None;
+    for (i, &action) in moves.iter().enumerate() {
+        let undo = board.play_action(action);
         let v = if depth <= batch_depth {
-            -batch_negamax(board, depth - 1, evaluator, !player)
+            parent_score(_batch_negamax(
+                board,
+                depth - 1,
+                evaluator,
+                !player,
+                &mut budget.stats,
+            ))
         } else {
-            -_abnegamax(
+            parent_score(_abnegamax(
                 board,
-                -beta,
-                -alpha,
+                child_score(beta),
+                child_score(alpha),
                 depth - 1,
                 batch_depth,
                 evaluator,
                 !player,
                 tt,
-            )
+                budget,
+            ))
         };
-        val = val.max(v);
-        board.reverse_last_action(action);
+        board.reverse_last_action(undo);
+        if budget.stopped {
+            return 0.0;
+        }
+        if v > val || best.is_none() {
+            val = v;
+            best = Some(action);
+        }
         alpha = alpha.max(val);
         if alpha >= beta {
+            budget.stats.beta_cutoff(i);
             break;
         }
     }
-    tt.set(board.uid(), alpha);
-    alpha
+    let bound = if val <= searched_alpha {
+        Bound::Upper
+    } else if val >= beta {
+        Bound::Lower
+    } else {
+        Bound::Exact
+    };
+    tt.store(board.key(), board.uid(), depth, val, bound, best);
+    val
 }
 
-pub fn batch_negamax<T, E>(board: &T, depth: u32, evaluator: &E, player: Player) -> f64
+pub fn batch_negamax<T, E>(
+    board: &T,
+    depth: u32,
+    evaluator: &E,
+    player: Player,
+) -> (f64, SearchStats)
+where
+    T: Game,
+    E: Evaluator<T>,
+    T::Action: Copy,
+{
+    let start = Instant::now();
+    let mut stats = SearchStats::default();
+    let value = _batch_negamax(board, depth, evaluator, player, &mut stats);
+    stats.elapsed = start.elapsed();
+    (value, stats)
+}
+
+fn _batch_negamax<T, E>(
+    board: &T,
+    depth: u32,
+    evaluator: &E,
+    player: Player,
+    stats: &mut SearchStats,
+) -> f64
 where
     T: Game,
     E: Evaluator<T>,
@@ -265,10 +816,12 @@ where
 {
     let mut _board = board.clone();
     let leafs = leafs(&mut _board, depth);
-    let mut vals: HashMap<u128, f64> = HashMap::new();
+    let mut vals: HashMap<T::Uid, f64> = HashMap::new();
     let mut leaf_vals = Vec::with_capacity(leafs.len());
 
     // compute leaf values in batch
+    stats.batches += 1;
+    stats.batched_positions += leafs.len() as u64;
     if depth % 2 == 0 {
         leaf_vals.append(&mut evaluator.values(&leafs, player));
     } else {
@@ -279,7 +832,7 @@ where
         vals.insert(leafs[i].uid(), *leaf_val);
     }
 
-    negamax_from_hashmap(&mut _board, depth, evaluator, player, &vals)
+    negamax_from_hashmap(&mut _board, depth, evaluator, player, &vals, stats)
 }
 
 fn negamax_from_hashmap<T, E>(
@@ -287,25 +840,39 @@ fn negamax_from_hashmap<T, E>(
     depth: u32,
     evaluator: &E,
     player: Player,
-    hmap: &HashMap<u128, f64>,
+    hmap: &HashMap<T::Uid, f64>,
+    stats: &mut SearchStats,
 ) -> f64
 where
     T: Game,
     E: Evaluator<T>,
     T::Action: Copy,
 {
+    stats.nodes += 1;
     if board.game_state() != GameState::InProgress {
-        return evaluator.value(board, player);
+        stats.leaves += 1;
+        stats.evaluations += 1;
+        return leaf_score(evaluator.value(board, player));
     }
     if depth == 0 {
-        return hmap[&board.uid()];
+        stats.leaves += 1;
+        return leaf_score(hmap[&board.uid()]);
     }
+    let mut moves = MoveList::new();
+    board.generate_moves(&mut moves);
     let mut val: f64 = -1. / 0.;
-    for action in board.legal_actions() {
-        board.play_action(action);
-        let v = -negamax_from_hashmap(board, depth - 1, evaluator, !player, &hmap);
+    for &action in moves.iter() {
+        let undo = board.play_action(action);
+        let v = parent_score(negamax_from_hashmap(
+            board,
+            depth - 1,
+            evaluator,
+            !player,
+            &hmap,
+            stats,
+        ));
         val = val.max(v);
-        board.reverse_last_action(action);
+        board.reverse_last_action(undo);
     }
     val
 }
@@ -321,11 +888,13 @@ where
     if board.game_state() != GameState::InProgress {
         return Vec::new();
     }
+    let mut moves = MoveList::new();
+    board.generate_moves(&mut moves);
     let mut ret = Vec::new();
-    for action in board.legal_actions() {
-        board.play_action(action);
+    for &action in moves.iter() {
+        let undo = board.play_action(action);
         ret.append(&mut leafs(board, depth - 1));
-        board.reverse_last_action(action);
+        board.reverse_last_action(undo);
     }
     ret
 }
@@ -337,11 +906,291 @@ mod test {
     use crate::games::Game;
     #[test]
     fn transposition_table() {
-        let mut tt: TranspositionTable<f64> = TranspositionTable::new();
+        let mut tt = TranspositionTable::new();
+        let mut board = Connect4::new();
+        board.play_action(4);
+        board.play_action(5);
+        let (key, uid) = (board.key(), board.uid());
+        tt.store(key, uid, 3, 1.0, Bound::Lower, Some(2));
+        let entry = tt.get(key, uid).unwrap();
+        assert_eq!((entry.depth, entry.value), (3, 1.0));
+        assert_eq!((entry.bound, entry.best), (Bound::Lower, Some(2)));
+        assert!(tt.get(key, uid + 1).is_none());
+
+        // a shallower entry for another position in the same bucket doesn't replace the deep
+        // one, it replaces the other shallow one instead.
+        tt.store(key, uid + 1, 1, 0.5, Bound::Exact, None);
+        assert!(tt.get(key, uid).is_some());
+        assert!(tt.get(key, uid + 1).is_some());
+        tt.store(key, uid + 2, 1, 0.0, Bound::Upper, None);
+        assert!(tt.get(key, uid).is_some());
+        assert!(tt.get(key, uid + 1).is_none());
+        // a deeper one takes the place of the deep entry.
+        tt.store(key, uid + 3, 4, 0.0, Bound::Exact, None);
+        assert!(tt.get(key, uid).is_none());
+        assert_eq!(tt.get(key, uid + 3).unwrap().depth, 4);
+
+        // once the deep entry is from an earlier search a shallower one replaces it.
+        tt.new_search();
+        tt.store(key, uid + 4, 1, 0.0, Bound::Upper, None);
+        assert!(tt.get(key, uid + 3).is_none());
+        assert_eq!(tt.get(key, uid + 4).unwrap().age, 1);
+    }
+
+    // Gives every position its own value so that alpha-beta has to get every bound right to
+    // agree with negamax.
+    struct KeyEval;
+
+    impl<G: Game> Evaluator<G> for KeyEval {
+        fn value(&self, board: &G, player: Player) -> f64 {
+            match board.game_state() {
+                GameState::Won(p) if p == player => 1. / 0.,
+                GameState::Won(_) => -1. / 0.,
+                GameState::Draw => 0.0,
+                GameState::InProgress => {
+                    let v = (board.key() % 97) as f64 - 48.0;
+                    if board.cur_player() == player {
+                        v
+                    } else {
+                        -v
+                    }
+                }
+            }
+        }
+        fn gradient(&self, _board: &G, _player: Player) -> Vec<f64> {
+            unimplemented!()
+        }
+        fn apply_update(&mut self, _update: &[f64]) {
+            unimplemented!()
+        }
+        fn get_params(&self) -> Vec<f64> {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn shared_table_agrees_with_negamax() {
+        let mut board = Connect4::new();
+        for action in [3, 3, 2, 4] {
+            board.play_action(action);
+        }
+        let player = board.cur_player();
+        let actions: Vec<_> = board.legal_actions().collect();
+        // the table is shared between the root moves, searched forwards and backwards.
+        for order in [actions.clone(), actions.iter().rev().copied().collect()] {
+            let mut tt = TranspositionTable::new();
+            for &action in &order {
+                board.play_action(action);
+                let (v, _) = abnegamax(&board, 4, 0, &KeyEval, !player, Some(&mut tt));
+                let (expected, _) = negamax(&mut board, 4, &KeyEval, !player);
+                assert_eq!(v, expected, "after {}", action);
+                board.reverse_last_action(action);
+            }
+        }
+    }
+
+    #[test]
+    fn narrowed_window_bound() {
+        // a lower bound from a deeper search that is above every value at this depth.
+        let board = Connect4::new();
+        let mut tt = TranspositionTable::new();
+        tt.store(board.key(), board.uid(), 6, 100.0, Bound::Lower, None);
+        let (v, _) = abnegamax(&board, 2, 0, &KeyEval, Player::Red, Some(&mut tt));
+        assert!(v < 100.0);
+        let entry = tt.get(board.key(), board.uid()).unwrap();
+        assert_eq!((entry.depth, entry.bound), (2, Bound::Upper));
+    }
+
+    #[test]
+    fn iterative_deepening_agrees_with_negamax() {
         let mut board = Connect4::new();
+        for action in [3, 2, 4, 4] {
+            board.play_action(action);
+        }
+        let player = board.cur_player();
+        let actions: Vec<_> = board.legal_actions().collect();
+        let limits = SearchLimits::default();
+        let result = iterative_deepening(&board, &actions, 4, 0, &KeyEval, player, &limits, None);
+        assert_eq!(result.depth, 4);
+        assert_eq!(result.root_scores.len(), actions.len());
+        for root in &result.root_scores {
+            board.play_action(root.action);
+            assert_eq!(
+                root.value,
+                parent_score(negamax(&mut board, 3, &KeyEval, !player).0)
+            );
+            assert_eq!(root.bound, Bound::Exact);
+            board.reverse_last_action(root.action);
+        }
+        assert_eq!(result.value, result.root_scores[0].value);
+        assert_eq!(result.pv[0], result.best);
+        assert!(result.pv.len() <= 4);
+
+        // a table kept from the search of an earlier position gives the same values.
+        let mut tt = TranspositionTable::new();
+        board.reverse_last_action(4);
+        let earlier: Vec<_> = board.legal_actions().collect();
+        iterative_deepening(
+            &board,
+            &earlier,
+            5,
+            0,
+            &KeyEval,
+            !player,
+            &limits,
+            Some(&mut tt),
+        );
         board.play_action(4);
+        let kept = iterative_deepening(
+            &board,
+            &actions,
+            4,
+            0,
+            &KeyEval,
+            player,
+            &limits,
+            Some(&mut tt),
+        );
+        for root in &result.root_scores {
+            let kept_root = kept.root_scores.iter().find(|r| r.action == root.action);
+            assert_eq!(root.value, kept_root.unwrap().value);
+        }
+    }
+
+    #[test]
+    fn search_limits() {
+        // red wins with 0 or 4.
+        let mut board = Connect4::new();
+        for action in [1, 1, 2, 2, 3, 3] {
+            board.play_action(action);
+        }
+        let player = board.cur_player();
+        let limited = [
+            SearchLimits::time(Duration::ZERO),
+            SearchLimits::default().with_max_nodes(1),
+            SearchLimits::default().with_cancel(Arc::new(AtomicBool::new(true))),
+        ];
+        for limits in &limited {
+            // the first iteration always finishes and finds the win.
+            let result = search_position(&board, MAX_DEPTH, 0, &KeyEval, player, limits);
+            assert!(result.best == 0 || result.best == 4);
+            assert_eq!(result.depth, 1);
+        }
+
+        // without a win the values are the ones of the first iteration.
+        let board = Connect4::new();
+        let actions: Vec<_> = board.legal_actions().collect();
+        let limits = &limited[1];
+        let avs = iterative_deepening(&board, &actions, 3, 0, &KeyEval, Player::Red, limits, None);
+        let first =
+            iterative_deepening(&board, &actions, 1, 0, &KeyEval, Player::Red, limits, None);
+        assert_eq!(avs.root_scores, first.root_scores);
+        assert_eq!(avs.depth, 1);
+
+        // batches count many nodes at once, the limits are still checked.
+        let result = search_position(&board, MAX_DEPTH, 3, &KeyEval, Player::Red, &limited[2]);
+        assert!(result.depth < 5);
+    }
+
+    #[test]
+    fn search_result() {
+        // yellow can only stop one of red's threats.
+        let mut board = Connect4::new();
+        for action in [2, 2, 3, 3] {
+            board.play_action(action);
+        }
+        let limits = SearchLimits::default();
+        let result = search_position(&board, 3, 0, &KeyEval, Player::Red, &limits);
+        assert_eq!(result.value, win_in(3));
+        assert!(result.best == 1 || result.best == 4);
+        assert_eq!(result.pv.len(), 3);
+        assert_eq!(result.score(), "win in 3");
+        let shown = format!("{:#}", result);
+        assert!(shown.starts_with(&format!("{} win in 3 depth 3", result.best)));
+        assert_eq!(shown.lines().count(), 1 + 7 + 1);
+
+        let result = search_position(&Connect4::new(), 2, 0, &KeyEval, Player::Red, &limits);
+        assert_eq!(moves_to_end(result.value), None);
+        assert_eq!(result.score(), format!("{:+.3}", result.value));
+    }
+
+    #[test]
+    fn scores() {
+        assert_eq!(moves_to_end(win_in(7)), Some(7));
+        assert_eq!(moves_to_end(loss_in(4)), Some(4));
+        assert_eq!(moves_to_end(0.5), None);
+        assert!(win_in(1) > win_in(3) && loss_in(3) > loss_in(1));
+        assert!(is_win(win_in(0)) && !is_win(0.5) && is_loss(loss_in(0)));
+        assert_eq!(format_score(win_in(5)), "win in 5");
+        assert_eq!(format_score(loss_in(2)), "loss in 2");
+        assert_eq!(parent_score(loss_in(2)), win_in(3));
+        assert_eq!(parent_score(win_in(2)), loss_in(3));
+        assert_eq!(parent_score(child_score(win_in(3))), win_in(3));
+        assert_eq!(parent_score(-0.25), 0.25);
+    }
+
+    #[test]
+    fn fastest_win() {
+        // red wins at once with 0 or 4, and with 5 a move later since yellow can't block both.
+        let mut board = Connect4::new();
+        for action in [1, 1, 2, 2, 3, 3] {
+            board.play_action(action);
+        }
+        let mut avs = Vec::new();
+        for action in board.legal_actions() {
+            board.play_action(action);
+            let (value, _) = negamax(&mut board, 2, &KeyEval, Player::Yellow);
+            avs.push((action, parent_score(value)));
+            board.reverse_last_action(action);
+        }
+        assert!(avs.contains(&(0, win_in(1))) && avs.contains(&(5, win_in(3))));
+        for _ in 0..10 {
+            let best = choose_best(&avs);
+            assert!(best == 0 || best == 4);
+        }
+        let result = batch_negamax_best_action(&board, 3, &KeyEval, Player::Red);
+        assert!(result.best == 0 || result.best == 4);
+        assert_eq!(result.score(), "win in 1");
+
+        let mut tt = TranspositionTable::new();
         board.play_action(5);
-        tt.set(board.uid(), 1.0);
-        assert_eq!(tt.get(board.uid()), Some(1.0));
+        let (value, _) = abnegamax(&board, 2, 0, &KeyEval, Player::Yellow, Some(&mut tt));
+        assert_eq!(parent_score(value), win_in(3));
+        // the table holds the score of the position it was searched from.
+        let entry = tt.get(board.key(), board.uid()).unwrap();
+        assert_eq!(entry.value, loss_in(2));
+    }
+
+    #[test]
+    fn search_stats() {
+        let board = Connect4::new();
+        let limits = SearchLimits::default();
+        let stats = search_position(&board, 5, 0, &KeyEval, Player::Red, &limits).stats;
+        assert!(stats.nodes > stats.leaves && stats.leaves > 0);
+        assert_eq!(stats.evaluations, stats.leaves);
+        assert_eq!(stats.batches, 0);
+        assert!(stats.tt_probes >= stats.tt_hits && stats.tt_hits >= stats.tt_cutoffs);
+        assert!(stats.tt_cutoffs > 0);
+        assert!(stats.beta_cutoffs[0] > 0);
+
+        // the positions after the second move are evaluated in one batch per first move.
+        let (_, batched) = abnegamax(&board, 2, 2, &KeyEval, Player::Red, None);
+        assert_eq!(batched.batches, 7);
+        assert_eq!(batched.batched_positions, 49);
+        assert_eq!(batched.leaves, 49);
+
+        let (value, full) = negamax(&mut board.clone(), 2, &KeyEval, Player::Red);
+        assert_eq!(
+            (full.nodes, full.leaves, full.evaluations),
+            (1 + 7 + 49, 49, 49)
+        );
+        let (batch_value, one_batch) = batch_negamax(&board, 2, &KeyEval, Player::Red);
+        assert_eq!(batch_value, value);
+        assert_eq!((one_batch.batches, one_batch.batched_positions), (1, 49));
+
+        let mut merged = stats.clone();
+        merged.merge(&batched);
+        assert_eq!(merged.nodes, stats.nodes + batched.nodes);
+        assert_eq!(merged.beta_cutoffs.len(), stats.beta_cutoffs.len());
     }
 }